@@ -1,4 +1,4 @@
-local url, clientName = ...
+local url, clientName, secret = ...
 local terms = { term.native(), peripheral.find 'monitor' }
 
 for _, term in ipairs(terms) do
@@ -87,6 +87,153 @@ local function dec(h)
   return function(x) s(x) end
 end
 
+-- Compact encoding (protocol 2), see `lua_value::serialize_compact`.
+local function varint(x)
+  local r = ''
+  while x >= 128 do r = r .. string.char(x % 128 + 128) x = math.floor(x / 128) end
+  return r .. string.char(x)
+end
+
+local function cenc(x)
+  local r, strs, n = {}, {}, 0
+  local function e(x)
+    local t = type(x)
+    if t == 'nil' then r[#r + 1] = '\0'
+    elseif t == 'boolean' then if x then r[#r + 1] = '\2' else r[#r + 1] = '\1' end
+    elseif t == 'number' then
+      if x >= 0 and x < 2 ^ 53 and x % 1 == 0 then r[#r + 1] = '\4' .. varint(x)
+      else local s = string.format('%.17g', x) r[#r + 1] = '\3' .. varint(#s) .. s end
+    elseif t == 'string' then
+      if strs[x] then r[#r + 1] = '\6' .. varint(strs[x])
+      else strs[x] = n n = n + 1 r[#r + 1] = '\5' .. varint(#x) .. x end
+    else
+      r[#r + 1] = '\7'
+      for k, v in pairs(x) do e(k) e(v) end
+      r[#r + 1] = '\0'
+    end
+  end
+  e(x)
+  return table.concat(r)
+end
+
+local function cdec(h)
+  local buf = ''
+  return function(x)
+    buf = buf .. x
+    while #buf > 0 do
+      local i, strs = 1, {}
+      local function more() error(cdec, 0) end
+      local function byte()
+        local b = string.byte(buf, i)
+        if not b then more() end
+        i = i + 1
+        return b
+      end
+      local function uint()
+        local r, m, b = 0, 1
+        repeat b = byte() r = r + b % 128 * m m = m * 128 until b < 128
+        return r
+      end
+      local function bytes(n)
+        if i + n - 1 > #buf then more() end
+        i = i + n
+        return string.sub(buf, i - n, i - 1)
+      end
+      local function v()
+        local t = byte()
+        if t == 0 then return nil
+        elseif t == 1 then return false
+        elseif t == 2 then return true
+        elseif t == 3 then return tonumber(bytes(uint()))
+        elseif t == 4 then return uint()
+        elseif t == 5 then local s = bytes(uint()) strs[#strs + 1] = s return s
+        elseif t == 6 then return strs[uint() + 1]
+        elseif t == 7 then
+          local r = {}
+          while true do
+            local k = v()
+            if k == nil then return r end
+            r[k] = v()
+          end
+        else error('invalid tag: ' .. t) end
+      end
+      local e, r = pcall(v)
+      if e then buf = string.sub(buf, i) h(r)
+      elseif r == cdec then return
+      else error(r, 0) end
+    end
+  end
+end
+
+local sha256 (function()
+  local k = {
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+  }
+  local function rr(x, n) return bit.bor(bit.brshift(x, n), bit.blshift(x, 32 - n)) end
+  local function x3(a, b, c) return bit.bxor(bit.bxor(a, b), c) end
+  function sha256(m)
+    local h = { 0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19 }
+    local n = #m * 8
+    m = m .. '\128' .. string.rep('\0', (55 - #m) % 64)
+    for i = 7, 0, -1 do m = m .. string.char(math.floor(n / 2 ^ (i * 8)) % 256) end
+    for p = 1, #m, 64 do
+      local w = {}
+      for i = 1, 16 do
+        local a, b, c, d = string.byte(m, p + i * 4 - 4, p + i * 4 - 1)
+        w[i] = ((a * 256 + b) * 256 + c) * 256 + d
+      end
+      for i = 17, 64 do
+        local s0 = x3(rr(w[i - 15], 7), rr(w[i - 15], 18), bit.brshift(w[i - 15], 3))
+        local s1 = x3(rr(w[i - 2], 17), rr(w[i - 2], 19), bit.brshift(w[i - 2], 10))
+        w[i] = (w[i - 16] + s0 + w[i - 7] + s1) % 2 ^ 32
+      end
+      local a, b, c, d, e, f, g, hh = table.unpack(h)
+      for i = 1, 64 do
+        local ch = bit.bxor(bit.band(e, f), bit.band(bit.bnot(e), g))
+        local t1 = (hh + x3(rr(e, 6), rr(e, 11), rr(e, 25)) + ch + k[i] + w[i]) % 2 ^ 32
+        local t2 = x3(rr(a, 2), rr(a, 13), rr(a, 22)) + x3(bit.band(a, b), bit.band(a, c), bit.band(b, c))
+        a, b, c, d, e, f, g, hh = (t1 + t2) % 2 ^ 32, a, b, c, (d + t1) % 2 ^ 32, e, f, g
+      end
+      for i, x in ipairs { a, b, c, d, e, f, g, hh } do h[i] = (h[i] + x) % 2 ^ 32 end
+    end
+    local r = ''
+    for _, x in ipairs(h) do
+      for i = 3, 0, -1 do r = r .. string.char(math.floor(x / 2 ^ (i * 8)) % 256) end
+    end
+    return r
+  end
+end)()
+
+-- Response to the server's login challenge: hex of HMAC-SHA256(secret, nonce).
+local function respond(nonce)
+  local key = secret
+  if #key > 64 then key = sha256(key) end
+  key = key .. string.rep('\0', 64 - #key)
+  local function pad(c) return (string.gsub(key, '.', function(x) return string.char(bit.bxor(x:byte(), c)) end)) end
+  local mac = sha256(pad(0x5c) .. sha256(pad(0x36) .. nonce))
+  return (string.gsub(mac, '.', function(x) return string.format('%02x', x:byte()) end))
+end
+
+-- Reported at login, see `server::Capabilities`.
+local function capabilities()
+  local r = { turtle = turtle ~= nil, batch = true, id = os.getComputerID(), peripherals = {} }
+  for _, name in ipairs(peripheral.getNames()) do
+    r.peripherals[name] = peripheral.getType(name)
+    for _, m in ipairs(peripheral.getMethods(name) or {}) do
+      if m == 'getItemDetail' then r.api = 'generic'
+      elseif m == 'getItemMeta' and not r.api then r.api = 'plethora' end
+    end
+  end
+  return r
+end
+
 local function callRS(p, f, ...)
   if p then return peripheral.call(p, f, ...)
   else return rs[f](...) end
@@ -105,7 +252,21 @@ local function exec(p, r)
       if p.v ~= 0 then v = bit.bor(v, p.b) else v = bit.band(v, bit.bnot(p.b)) end
       callRS(p.p, 'setBundledOutput', p.s, v)
     else callRS(p.p, 'setAnalogOutput', p.s, p.v) end
+  elseif p.o == 'm' then
+    -- The same method with each list of arguments, concurrently; errors are returned as strings.
+    local fs = {}
+    r.r = {}
+    for i, v in ipairs(p.v) do
+      fs[i] = function()
+        local x = { pcall(peripheral.call, p.p, p.m, table.unpack(v)) }
+        if table.remove(x, 1) then r.r[i] = x else r.r[i] = tostring(x[1]) end
+      end
+    end
+    parallel.waitForAll(table.unpack(fs))
   elseif p.o == 't' then r.r = { turtle[p.f](table.unpack(p.v)) }
+  elseif p.o == 'p' then
+    r.r = {}
+    for _, name in ipairs(peripheral.getNames()) do r.r[name] = { peripheral.getType(name) } end
   else error('invalid op: ' .. tostring(p.o)) end
   return 0
 end
@@ -131,8 +292,17 @@ while true do
   end
   if socket then
     log { t = 'Connected', c = 13 }
-    local out, tasks = enc(clientName), {}
-    local handler = dec(function(p)
+    -- Offer the compact encoding; the server answers with the protocol to use from then on.
+    local out, tasks, encode, handler = enc { n = clientName, v = 2, c = capabilities() }, {}, enc
+    local function onPacket(p)
+      if type(p) == 'string' then
+        if secret then out = out .. encode(respond(p))
+        else log { t = 'Server requires a secret', c = 14 } end
+        return
+      elseif p.v then
+        if p.v >= 2 then encode, handler = cenc, cdec(onPacket) end
+        return
+      end
       for _, p in ipairs(p) do
         local task = coroutine.create(exec)
         local r = { i = p.i }
@@ -140,11 +310,12 @@ while true do
         if not e then
           r.r = nil
           r.e = d
-          out = out .. enc(r)
-        elseif type(d) == 'number' then out = out .. enc(r)
+          out = out .. encode(r)
+        elseif type(d) == 'number' then out = out .. encode(r)
         else tasks[#tasks + 1] = { task = task, filter = d, r = r } end
       end
-    end)
+    end
+    handler = dec(onPacket)
     while true do
       local e = true
       while #out > 0 do
@@ -170,8 +341,8 @@ while true do
           if not e then
             v.r.r = nil
             v.r.e = d
-            out = out .. enc(v.r)
-          elseif type(d) == 'number' then out = out .. enc(v.r)
+            out = out .. encode(v.r)
+          elseif type(d) == 'number' then out = out .. encode(v.r)
           else newTasks[#newTasks + 1] = { task = v.task, filter = d, r = v.r } end
         else newTasks[#newTasks + 1] = v end
       end
@@ -1,4 +1,5 @@
 use flexstr::LocalStr;
+use std::collections::{BTreeMap, BTreeSet};
 
 pub trait GetClient {
     fn get_client(&self) -> &str;
@@ -37,6 +38,14 @@ impl GetAddr for BusAccess {
     fn get_addr(&self) -> &LocalStr { &self.inv_addr }
 }
 
+// A storage bridge (e.g. an ME or RS bridge) that moves items by type between its network and the bus.
+impl_get_client!(BridgeAccess);
+pub struct BridgeAccess {
+    pub client: LocalStr,
+    pub bridge_addr: LocalStr,
+    pub bus_addr: LocalStr,
+}
+
 impl_get_client!(RedstoneAccess);
 pub struct RedstoneAccess {
     pub client: LocalStr,
@@ -92,3 +101,119 @@ pub struct InvTankAccess {
     pub bus_addr: LocalStr,
     pub fluid_bus_addrs: Vec<LocalStr>,
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PeripheralKind {
+    Inventory,
+    Tank,
+    Redstone,
+    Bridge,
+}
+
+impl PeripheralKind {
+    // The type that generic peripherals report for this kind, if any.
+    pub fn generic_type(self) -> Option<&'static str> {
+        match self {
+            PeripheralKind::Inventory => Some("inventory"),
+            PeripheralKind::Tank => Some("fluid_storage"),
+            PeripheralKind::Redstone | PeripheralKind::Bridge => None,
+        }
+    }
+}
+
+// Peripherals that an access calls on its client, checked against the attached ones when the client logs in.
+pub trait GetPeripherals: GetClient {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)>;
+}
+
+fn inventories(addrs: &[LocalStr]) -> impl Iterator<Item = (&LocalStr, PeripheralKind)> {
+    addrs.iter().map(|x| (x, PeripheralKind::Inventory))
+}
+
+fn tanks(addrs: &[LocalStr]) -> impl Iterator<Item = (&LocalStr, PeripheralKind)> {
+    addrs.iter().map(|x| (x, PeripheralKind::Tank))
+}
+
+impl GetPeripherals for BasicAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> { vec![(&self.addr, PeripheralKind::Inventory)] }
+}
+
+impl GetPeripherals for BusAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        vec![(&self.inv_addr, PeripheralKind::Inventory), (&self.bus_addr, PeripheralKind::Inventory)]
+    }
+}
+
+impl GetPeripherals for BridgeAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        vec![(&self.bridge_addr, PeripheralKind::Bridge), (&self.bus_addr, PeripheralKind::Inventory)]
+    }
+}
+
+// Without `addr`, the redstone is the computer's own.
+impl GetPeripherals for RedstoneAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        Vec::from_iter(self.addr.iter().map(|x| (x, PeripheralKind::Redstone)))
+    }
+}
+
+// `turtle_addr` is the turtle's name as seen from the bus, which the turtle itself doesn't see.
+impl GetPeripherals for CraftyAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        vec![(&self.non_consumable_addr, PeripheralKind::Inventory), (&self.bus_addr, PeripheralKind::Inventory)]
+    }
+}
+
+impl GetPeripherals for MultiInvAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        inventories(&self.inv_addrs).chain(inventories(std::slice::from_ref(&self.bus_addr))).collect()
+    }
+}
+
+impl GetPeripherals for FluidAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> { tanks(&self.fluid_bus_addrs).collect() }
+}
+
+impl GetPeripherals for TankAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        tanks(std::slice::from_ref(&self.tank_addr)).chain(tanks(&self.fluid_bus_addrs)).collect()
+    }
+}
+
+impl GetPeripherals for InvTankAccess {
+    fn get_peripherals(&self) -> Vec<(&LocalStr, PeripheralKind)> {
+        let bus = inventories(std::slice::from_ref(&self.bus_addr));
+        inventories(&self.inv_addrs)
+            .chain(tanks(&self.tank_addrs))
+            .chain(bus)
+            .chain(tanks(&self.fluid_bus_addrs))
+            .collect()
+    }
+}
+
+// The peripherals that a layout expects on one client, by address, with the kind and the first user of each.
+pub struct ExpectedPeripherals {
+    pub client: LocalStr,
+    pub peripherals: BTreeMap<LocalStr, (PeripheralKind, LocalStr)>,
+    // Every user, on any client.
+    pub owners: BTreeSet<LocalStr>,
+}
+
+impl ExpectedPeripherals {
+    pub fn new(client: LocalStr) -> Self {
+        ExpectedPeripherals { client, peripherals: BTreeMap::new(), owners: BTreeSet::new() }
+    }
+
+    pub fn add<'a, T: GetPeripherals + 'a>(&mut self, owner: &str, accesses: impl IntoIterator<Item = &'a T>) {
+        if !self.owners.contains(owner) {
+            self.owners.insert(owner.into());
+        }
+        for access in accesses {
+            if access.get_client() == &*self.client {
+                for (addr, kind) in access.get_peripherals() {
+                    self.peripherals.entry(addr.clone()).or_insert_with(|| (kind, owner.into()));
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use super::lua_value::{vec_to_table, Table, Value};
-use flexstr::LocalStr;
+use super::lua_value::{table_to_vec, vec_to_table, Key, Table, Value};
+use flexstr::{local_fmt, local_str, LocalStr};
+use fnv::FnvHashMap;
 use std::{
     cell::RefCell,
     future::Future,
@@ -12,18 +13,40 @@ pub trait Action: 'static {
     type Output;
     fn build_request(self, table: &mut Table);
     fn parse_response(response: Value) -> Result<Self::Output, LocalStr>;
+    // Name used to look up the timeout, e.g. `list` or `turtle.craft`.
+    fn get_method(&self) -> LocalStr;
+    // Whether the action changes the world, and so is withheld in dry-run mode.
+    fn is_mutating(&self) -> bool { false }
+    // What a mutating action is assumed to return in dry-run mode.
+    fn dry_run_response(&self) -> Value { Table::new().into() }
+}
+
+// How a request failed along with its client.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Lost {
+    // Never sent, so it can safely go to another client.
+    Unsent,
+    // Sent without being answered; if the action is mutating, it may or may not have happened.
+    Unanswered,
 }
 
 struct ActionState<T: Action> {
     result: Option<Result<T::Output, LocalStr>>,
     waker: Option<Waker>,
     action: Option<T>,
+    lost: Option<Lost>,
 }
 
 pub trait ActionRequest {
     fn build_request(&mut self, table: &mut Table);
     fn on_fail(&mut self, reason: LocalStr);
+    fn on_lost(&mut self, reason: LocalStr, lost: Lost);
     fn on_response(&mut self, result: Value) -> Result<(), LocalStr>;
+    // These two are only valid before `build_request`.
+    fn get_method(&self) -> LocalStr;
+    fn is_mutating(&self) -> bool;
+    // For dry-run: completes a mutating action with its assumed result without sending it, and returns its request.
+    fn intercept(&mut self) -> Option<Table>;
 }
 
 impl<T: Action> ActionRequest for ActionState<T> {
@@ -36,6 +59,11 @@ impl<T: Action> ActionRequest for ActionState<T> {
         }
     }
 
+    fn on_lost(&mut self, reason: LocalStr, lost: Lost) {
+        self.lost = Some(lost);
+        self.on_fail(reason)
+    }
+
     fn on_response(&mut self, result: Value) -> Result<(), LocalStr> {
         let result = T::parse_response(result);
         let ret = if let Err(ref e) = result { Err(e.clone()) } else { Ok(()) };
@@ -45,10 +73,30 @@ impl<T: Action> ActionRequest for ActionState<T> {
         }
         ret
     }
+
+    fn get_method(&self) -> LocalStr { self.action.as_ref().unwrap().get_method() }
+    fn is_mutating(&self) -> bool { self.action.as_ref().unwrap().is_mutating() }
+
+    fn intercept(&mut self) -> Option<Table> {
+        if !self.is_mutating() {
+            return None;
+        }
+        let action = self.action.take().unwrap();
+        let response = action.dry_run_response();
+        let mut table = Table::new();
+        action.build_request(&mut table);
+        let _ = self.on_response(response);
+        Some(table)
+    }
 }
 
 pub struct ActionFuture<T: Action>(Rc<RefCell<ActionState<T>>>);
 
+impl<T: Action> ActionFuture<T> {
+    // Set once the future fails because its client disconnected.
+    pub fn get_lost(&self) -> Option<Lost> { self.0.borrow().lost }
+}
+
 impl<T: Action> Clone for ActionFuture<T> {
     fn clone(&self) -> Self { ActionFuture(self.0.clone()) }
 }
@@ -69,7 +117,7 @@ impl<T: Action> Future for ActionFuture<T> {
 
 impl<T: Action> From<T> for ActionFuture<T> {
     fn from(action: T) -> Self {
-        ActionFuture(Rc::new(RefCell::new(ActionState { result: None, waker: None, action: Some(action) })))
+        ActionFuture(Rc::new(RefCell::new(ActionState { result: None, waker: None, action: Some(action), lost: None })))
     }
 }
 
@@ -93,6 +141,26 @@ impl Action for Log {
     }
 
     fn parse_response(_: Value) -> Result<(), LocalStr> { Ok(()) }
+    fn get_method(&self) -> LocalStr { local_str!("log") }
+}
+
+const MUTATING_METHODS: [&str; 6] = ["pushItems", "pullItems", "pushFluid", "pullFluid", "importItem", "exportItem"];
+
+// Assumes a transfer moves everything requested, returned the way the client wraps results (`{1 = n}`).
+fn dry_run_transfer(args: &[Value]) -> Value {
+    let Some(Value::S(method)) = args.first() else { return Table::new().into() };
+    let n = match &**method {
+        "pushItems" | "pullItems" => args.get(3).cloned(),
+        "pushFluid" | "pullFluid" => args.get(2).cloned(),
+        "importItem" | "exportItem" => match args.get(1) {
+            Some(Value::T(filter)) => filter.get(&"count".into()).cloned(),
+            _ => None,
+        },
+        _ => None,
+    };
+    // Without a limit the amount is unknown, so nothing is assumed to move.
+    let n = n.filter(|x| matches!(x, Value::F(_))).unwrap_or_else(|| 0.into());
+    vec_to_table(vec![n]).into()
 }
 
 pub struct Call {
@@ -110,6 +178,59 @@ impl Action for Call {
     }
 
     fn parse_response(response: Value) -> Result<Value, LocalStr> { Ok(response) }
+
+    fn get_method(&self) -> LocalStr {
+        let Some(Value::S(method)) = self.args.first() else { return local_str!("call") };
+        method.clone()
+    }
+
+    fn is_mutating(&self) -> bool {
+        let Some(Value::S(method)) = self.args.first() else { return false };
+        MUTATING_METHODS.contains(&&**method)
+    }
+
+    fn dry_run_response(&self) -> Value { dry_run_transfer(&self.args) }
+}
+
+// Calls the same method with each list of arguments concurrently, e.g. `getItemDetail` for many slots at once.
+pub struct CallMany {
+    pub addr: LocalStr,
+    pub method: LocalStr,
+    pub args: Vec<Vec<Value>>,
+}
+
+impl Action for CallMany {
+    // Like the output of `Call`, or the error, for each list of arguments.
+    type Output = Vec<Result<Value, LocalStr>>;
+
+    fn build_request(self, table: &mut Table) {
+        table.insert("o".into(), "m".into());
+        table.insert("p".into(), self.addr.into());
+        table.insert("m".into(), self.method.into());
+        table.insert(
+            "v".into(),
+            vec_to_table(Vec::from_iter(self.args.into_iter().map(|x| vec_to_table(x).into()))).into(),
+        );
+    }
+
+    fn parse_response(response: Value) -> Result<Self::Output, LocalStr> {
+        let results = table_to_vec(response.try_into()?)?;
+        Ok(Vec::from_iter(results.into_iter().map(|x| match x {
+            Value::S(e) => Err(e),
+            x => Ok(x),
+        })))
+    }
+
+    fn get_method(&self) -> LocalStr { self.method.clone() }
+    fn is_mutating(&self) -> bool { MUTATING_METHODS.contains(&&*self.method) }
+
+    fn dry_run_response(&self) -> Value {
+        let responses = self.args.iter().map(|args| {
+            let args = Vec::from_iter(std::iter::once(self.method.clone().into()).chain(args.iter().cloned()));
+            dry_run_transfer(&args)
+        });
+        vec_to_table(responses.collect()).into()
+    }
 }
 
 pub struct RedstoneInput {
@@ -133,6 +254,7 @@ impl Action for RedstoneInput {
     }
 
     fn parse_response(response: Value) -> Result<u8, LocalStr> { response.try_into() }
+    fn get_method(&self) -> LocalStr { local_str!("redstone.input") }
 }
 
 pub struct RedstoneOutput {
@@ -158,6 +280,8 @@ impl Action for RedstoneOutput {
     }
 
     fn parse_response(_: Value) -> Result<(), LocalStr> { Ok(()) }
+    fn get_method(&self) -> LocalStr { local_str!("redstone.output") }
+    fn is_mutating(&self) -> bool { true }
 }
 
 pub struct TurtleCall {
@@ -175,4 +299,29 @@ impl Action for TurtleCall {
     }
 
     fn parse_response(response: Value) -> Result<Value, LocalStr> { Ok(response) }
+    fn get_method(&self) -> LocalStr { local_fmt!("turtle.{}", self.func) }
+
+    // Everything but queries (`getItemDetail`, `inspect`, ...) moves items or the turtle.
+    fn is_mutating(&self) -> bool { !["get", "inspect", "detect", "compare"].iter().any(|x| self.func.starts_with(x)) }
+}
+
+// Names and types of the peripherals attached to the client; generic peripherals can have several types.
+pub struct ListPeripherals;
+
+impl Action for ListPeripherals {
+    type Output = FnvHashMap<LocalStr, Vec<LocalStr>>;
+
+    fn build_request(self, table: &mut Table) { table.insert("o".into(), "p".into()); }
+
+    fn parse_response(response: Value) -> Result<Self::Output, LocalStr> {
+        let mut result = FnvHashMap::default();
+        for (name, types) in Table::try_from(response)? {
+            let Key::S(name) = name else { return Err(local_fmt!("invalid peripheral name: {:?}", name)) };
+            let types = table_to_vec(types.try_into()?)?;
+            result.insert(name, types.into_iter().map(LocalStr::try_from).collect::<Result<_, _>>()?);
+        }
+        Ok(result)
+    }
+
+    fn get_method(&self) -> LocalStr { local_str!("peripheral.getNames") }
 }
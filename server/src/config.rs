@@ -7,14 +7,19 @@ pub fn build_factory(tui: Rc<Tui>) -> Rc<RefCell<Factory>> {
     FactoryConfig {
         tui: tui.clone(),
         detail_cache: DetailCache::new(&tui, s("detail_cache.txt")),
-        server: Server::new(tui, 1847),
+        server: Server::new(tui, 1847, None),
         min_cycle_time: Duration::from_secs(1),
+        relist_interval: Duration::ZERO,
+        capacity_alerts: vec![90., 98.],
         log_clients: vec![s("1a")],
         bus_accesses: vec![BasicAccess { client: s("1a"), addr: s("enderstorage:ender_chest_1") }],
         fluid_bus_accesses: vec![],
         fluid_bus_capacity: 0,
         backups: vec![],
         fluid_backups: vec![],
+        history: None,
+        http_port: None,
+        journal: None,
     }
     .build(|factory| {
         factory.add_storage(ChestConfig {
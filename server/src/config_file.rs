@@ -0,0 +1,1225 @@
+use crate::access::*;
+use crate::factory::{Factory, FactoryConfig, FluidStorageConfig, Reloader};
+use crate::item::{Api, Detail, Filter, Item};
+use crate::process::*;
+use crate::recipe::*;
+use crate::server::{load_tls, Server, TimeoutConfig};
+use crate::storage::{BridgeConfig, ChestConfig, DrawerConfig, Route, TrashConfig};
+use crate::{detail_cache::DetailCache, history::HistoryConfig, Tui};
+use flexstr::{local_fmt, local_str, LocalStr};
+use fnv::FnvHashMap;
+use regex::Regex;
+use serde::Deserialize;
+use std::fs::{metadata, read_to_string};
+use std::{cell::RefCell, mem::replace, rc::Rc, time::Duration, time::SystemTime};
+
+// Declarative counterpart of `config::build_factory`, loaded from a JSON file.
+// All validation happens during deserialization so that errors carry line and column numbers.
+
+fn s(x: String) -> LocalStr { x.into() }
+fn ss(x: Vec<String>) -> Vec<LocalStr> { x.into_iter().map(s).collect() }
+fn v<T, U: From<T>>(x: Vec<T>) -> Vec<U> { x.into_iter().map(U::from).collect() }
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BasicAccessFile {
+    client: String,
+    addr: String,
+}
+
+impl From<BasicAccessFile> for BasicAccess {
+    fn from(x: BasicAccessFile) -> Self { BasicAccess { client: s(x.client), addr: s(x.addr) } }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BusAccessFile {
+    client: String,
+    inv_addr: String,
+    bus_addr: String,
+}
+
+impl From<BusAccessFile> for BusAccess {
+    fn from(x: BusAccessFile) -> Self {
+        BusAccess { client: s(x.client), inv_addr: s(x.inv_addr), bus_addr: s(x.bus_addr) }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BridgeAccessFile {
+    client: String,
+    bridge_addr: String,
+    bus_addr: String,
+}
+
+impl From<BridgeAccessFile> for BridgeAccess {
+    fn from(x: BridgeAccessFile) -> Self {
+        BridgeAccess { client: s(x.client), bridge_addr: s(x.bridge_addr), bus_addr: s(x.bus_addr) }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedstoneAccessFile {
+    client: String,
+    #[serde(default)]
+    addr: Option<String>,
+    side: String,
+    #[serde(default)]
+    bit: Option<u8>,
+}
+
+impl From<RedstoneAccessFile> for RedstoneAccess {
+    fn from(x: RedstoneAccessFile) -> Self {
+        RedstoneAccess { client: s(x.client), addr: x.addr.map(s), side: s(x.side), bit: x.bit }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CraftyAccessFile {
+    client: String,
+    non_consumable_addr: String,
+    turtle_addr: String,
+    bus_addr: String,
+}
+
+impl From<CraftyAccessFile> for CraftyAccess {
+    fn from(x: CraftyAccessFile) -> Self {
+        CraftyAccess {
+            client: s(x.client),
+            non_consumable_addr: s(x.non_consumable_addr),
+            turtle_addr: s(x.turtle_addr),
+            bus_addr: s(x.bus_addr),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MultiInvAccessFile {
+    client: String,
+    inv_addrs: Vec<String>,
+    bus_addr: String,
+}
+
+impl From<MultiInvAccessFile> for MultiInvAccess {
+    fn from(x: MultiInvAccessFile) -> Self {
+        MultiInvAccess { client: s(x.client), inv_addrs: ss(x.inv_addrs), bus_addr: s(x.bus_addr) }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FluidAccessFile {
+    client: String,
+    fluid_bus_addrs: Vec<String>,
+}
+
+impl From<FluidAccessFile> for FluidAccess {
+    fn from(x: FluidAccessFile) -> Self { FluidAccess { client: s(x.client), fluid_bus_addrs: ss(x.fluid_bus_addrs) } }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TankAccessFile {
+    client: String,
+    tank_addr: String,
+    fluid_bus_addrs: Vec<String>,
+}
+
+impl From<TankAccessFile> for TankAccess {
+    fn from(x: TankAccessFile) -> Self {
+        TankAccess { client: s(x.client), tank_addr: s(x.tank_addr), fluid_bus_addrs: ss(x.fluid_bus_addrs) }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InvTankAccessFile {
+    client: String,
+    inv_addrs: Vec<String>,
+    tank_addrs: Vec<String>,
+    bus_addr: String,
+    fluid_bus_addrs: Vec<String>,
+}
+
+impl From<InvTankAccessFile> for InvTankAccess {
+    fn from(x: InvTankAccessFile) -> Self {
+        InvTankAccess {
+            client: s(x.client),
+            inv_addrs: ss(x.inv_addrs),
+            tank_addrs: ss(x.tank_addrs),
+            bus_addr: s(x.bus_addr),
+            fluid_bus_addrs: ss(x.fluid_bus_addrs),
+        }
+    }
+}
+
+// Durations are rejected unless finite and non-negative, instead of panicking in `Duration::from_secs_f64`.
+#[derive(Deserialize, PartialEq, Clone, Copy)]
+#[serde(try_from = "f64")]
+struct Seconds(Duration);
+
+impl TryFrom<f64> for Seconds {
+    type Error = LocalStr;
+    fn try_from(x: f64) -> Result<Self, LocalStr> {
+        Duration::try_from_secs_f64(x).map(Seconds).map_err(|_| local_fmt!("invalid duration: {}", x))
+    }
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy)]
+#[serde(try_from = "f64")]
+struct Hours(Duration);
+
+impl TryFrom<f64> for Hours {
+    type Error = LocalStr;
+    fn try_from(x: f64) -> Result<Self, LocalStr> { Seconds::try_from(x * 3600.).map(|x| Hours(x.0)) }
+}
+
+// Filters with only `label` and/or `name` map to the plain variants.
+// Anything else becomes a `Filter::Custom` matching when every given predicate holds.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterSpec {
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    label_regex: Option<String>,
+    #[serde(default)]
+    name_regex: Option<String>,
+    #[serde(default)]
+    has_nbt: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "FilterSpec")]
+struct FilterFile(Filter);
+
+impl TryFrom<FilterSpec> for FilterFile {
+    type Error = LocalStr;
+    fn try_from(x: FilterSpec) -> Result<Self, LocalStr> {
+        if x.label_regex.is_none() && x.name_regex.is_none() && x.has_nbt.is_none() {
+            return Ok(FilterFile(match (x.label, x.name) {
+                (Some(label), None) => Filter::Label(s(label)),
+                (None, Some(name)) => Filter::Name(s(name)),
+                (Some(label), Some(name)) => Filter::Both { label: s(label), name: s(name) },
+                (None, None) => return Err(LocalStr::from("empty filter")),
+            }));
+        }
+        let regex =
+            |x: Option<String>| x.map(|x| Regex::new(&x).map_err(|e| local_fmt!("invalid regex: {}", e))).transpose();
+        let mut desc = Vec::new();
+        desc.extend(x.label.as_ref().map(|x| format!("label={x}")));
+        desc.extend(x.name.as_ref().map(|x| format!("name={x}")));
+        desc.extend(x.label_regex.as_ref().map(|x| format!("label~{x}")));
+        desc.extend(x.name_regex.as_ref().map(|x| format!("name~{x}")));
+        desc.extend(x.has_nbt.map(|x| format!("has_nbt={x}")));
+        let (label, name, has_nbt) = (x.label, x.name, x.has_nbt);
+        let (label_regex, name_regex) = (regex(x.label_regex)?, regex(x.name_regex)?);
+        let func = move |item: &Item, detail: &Detail| {
+            label.as_ref().is_none_or(|x| detail.label == **x)
+                && name.as_ref().is_none_or(|x| item.name == **x)
+                && label_regex.as_ref().is_none_or(|x| x.is_match(&detail.label))
+                && name_regex.as_ref().is_none_or(|x| x.is_match(&item.name))
+                && has_nbt.is_none_or(|x| item.nbt_hash.is_some() == x)
+        };
+        Ok(FilterFile(Filter::Custom { desc: desc.join(",").into(), func: Rc::new(func) }))
+    }
+}
+
+fn filters(x: Vec<FilterFile>) -> Vec<Filter> { x.into_iter().map(|x| x.0).collect() }
+
+// Items per set, which the planner divides by.
+#[derive(Deserialize)]
+#[serde(try_from = "i32")]
+struct Yields(i32);
+
+impl TryFrom<i32> for Yields {
+    type Error = LocalStr;
+    fn try_from(x: i32) -> Result<Self, LocalStr> {
+        if x > 0 {
+            Ok(Yields(x))
+        } else {
+            Err(local_fmt!("yields must be positive: {}", x))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum OutputsFile {
+    Stock {
+        item: FilterFile,
+        n_wanted: i32,
+        #[serde(default)]
+        yields: Option<Yields>,
+    },
+    FluidStock {
+        fluid: String,
+        n_wanted: i64,
+    },
+    Ignore(f64),
+    And(Vec<OutputsFile>),
+    Or(Vec<OutputsFile>),
+    Not(Box<OutputsFile>),
+}
+
+impl From<OutputsFile> for Rc<dyn Outputs> {
+    fn from(x: OutputsFile) -> Self {
+        let fold = |xs: Vec<OutputsFile>, f: fn(Self, Self) -> Self| xs.into_iter().map(Self::from).reduce(f);
+        match x {
+            OutputsFile::Stock { item, n_wanted, yields: None } => Output::new(item.0, n_wanted),
+            OutputsFile::Stock { item, n_wanted, yields: Some(n) } => Output::new(item.0, n_wanted).yields(n.0),
+            OutputsFile::FluidStock { fluid, n_wanted } => FluidOutput::new(s(fluid), n_wanted),
+            OutputsFile::Ignore(priority) => ignore_outputs(priority),
+            OutputsFile::And(xs) => fold(xs, BoxedOutputs::and).unwrap_or_else(|| ignore_outputs(1.)),
+            OutputsFile::Or(xs) => fold(xs, BoxedOutputs::or).unwrap_or_else(|| ignore_outputs(1.).not()),
+            OutputsFile::Not(x) => Self::from(*x).not(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum SlotFilterFile {
+    Only(Vec<usize>),
+    Except(Vec<usize>),
+}
+
+impl From<SlotFilterFile> for SlotFilter {
+    fn from(x: SlotFilterFile) -> Self {
+        match x {
+            SlotFilterFile::Only(slots) => Box::new(move |slot| slots.contains(&slot)),
+            SlotFilterFile::Except(slots) => Box::new(move |slot| !slots.contains(&slot)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum ExtractFile {
+    All,
+    Slots(Vec<usize>),
+    Items(Vec<FilterFile>),
+    NotItems(Vec<FilterFile>),
+}
+
+type ExtractPred = Box<dyn Fn(usize, &Item, &Detail) -> bool>;
+
+impl ExtractFile {
+    fn into_pred(self) -> ExtractPred {
+        match self {
+            ExtractFile::All => Box::new(|_, _, _| true),
+            ExtractFile::Slots(slots) => Box::new(move |slot, _, _| slots.contains(&slot)),
+            ExtractFile::Items(items) => {
+                let items = filters(items);
+                Box::new(move |_, item, detail| items.iter().any(|x| x.apply(item, detail)))
+            }
+            ExtractFile::NotItems(items) => {
+                let items = filters(items);
+                Box::new(move |_, item, detail| !items.iter().any(|x| x.apply(item, detail)))
+            }
+        }
+    }
+}
+
+impl From<ExtractFile> for ExtractFilter {
+    fn from(x: ExtractFile) -> Self {
+        let pred = x.into_pred();
+        Box::new(move |_, slot, stack| pred(slot, &stack.item, &stack.detail))
+    }
+}
+
+impl From<ExtractFile> for MultiInvExtractFilter {
+    fn from(x: ExtractFile) -> Self {
+        let pred = x.into_pred();
+        Box::new(move |_, _, slot, stack| pred(slot, &stack.item, &stack.detail))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum FluidExtractFile {
+    All,
+    Tanks(Vec<usize>),
+}
+
+impl From<FluidExtractFile> for Option<FluidExtractFilter> {
+    fn from(x: FluidExtractFile) -> Self {
+        match x {
+            FluidExtractFile::All => fluid_extract_all(),
+            FluidExtractFile::Tanks(tanks) => fluid_extract_slots(move |_, tank| tanks.contains(&tank)),
+        }
+    }
+}
+
+// No `deny_unknown_fields`, which serde doesn't support along with `flatten`.
+#[derive(Deserialize)]
+struct InputFile<S> {
+    item: FilterFile,
+    #[serde(flatten)]
+    spec: S,
+    #[serde(default)]
+    allow_backup: bool,
+    #[serde(default)]
+    extra_backup: i32,
+}
+
+impl<S> InputFile<S> {
+    fn into_input<T: Input>(self, f: impl FnOnce(Filter, S) -> T) -> T {
+        let mut result = f(self.item.0, self.spec);
+        if self.allow_backup {
+            result = result.allow_backup()
+        }
+        result.extra_backup(self.extra_backup)
+    }
+}
+
+#[derive(Deserialize)]
+struct Size {
+    size: i32,
+}
+
+#[derive(Deserialize)]
+struct Slots<T> {
+    slots: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct NoSpec {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BufferedRecipeFile {
+    outputs: OutputsFile,
+    inputs: Vec<InputFile<Size>>,
+    max_inputs: i32,
+}
+
+fn buffered_input(x: InputFile<Size>) -> BufferedInput { x.into_input(|item, x| BufferedInput::new(item, x.size)) }
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SlottedRecipeFile {
+    outputs: OutputsFile,
+    inputs: Vec<InputFile<Slots<(usize, i32)>>>,
+    max_sets: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MultiInvSlottedRecipeFile {
+    outputs: OutputsFile,
+    inputs: Vec<InputFile<Slots<(usize, usize, i32)>>>,
+    max_sets: i32,
+}
+
+fn multi_inv_slotted_input(x: InputFile<Slots<(usize, usize, i32)>>) -> MultiInvSlottedInput {
+    x.into_input(|item, x| MultiInvSlottedInput::new(item, x.slots))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FluidSlottedInputFile {
+    fluid: String,
+    tanks: Vec<(usize, i64)>,
+    #[serde(default)]
+    allow_backup: bool,
+    #[serde(default)]
+    extra_backup: i64,
+}
+
+impl From<FluidSlottedInputFile> for FluidSlottedInput {
+    fn from(x: FluidSlottedInputFile) -> Self {
+        let mut result = FluidSlottedInput::new(s(x.fluid), x.tanks);
+        if x.allow_backup {
+            result = result.allow_backup()
+        }
+        result.extra_backup(x.extra_backup)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FluidSlottedRecipeFile {
+    outputs: OutputsFile,
+    inputs: Vec<InputFile<Slots<(usize, usize, i32)>>>,
+    fluids: Vec<FluidSlottedInputFile>,
+    max_sets: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScatteringRecipeFile {
+    outputs: OutputsFile,
+    input: InputFile<NoSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NonConsumableFile {
+    storage_slot: usize,
+    crafting_grid_slot: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CraftingGridRecipeFile {
+    outputs: OutputsFile,
+    inputs: Vec<InputFile<Slots<usize>>>,
+    max_sets: i32,
+    #[serde(default)]
+    non_consumables: Vec<NonConsumableFile>,
+}
+
+impl From<CraftingGridRecipeFile> for CraftingGridRecipe {
+    fn from(x: CraftingGridRecipeFile) -> Self {
+        CraftingGridRecipe {
+            outputs: x.outputs.into(),
+            inputs: Vec::from_iter(
+                x.inputs.into_iter().map(|x| x.into_input(|item, x| CraftingGridInput::new(item, x.slots))),
+            ),
+            max_sets: x.max_sets,
+            non_consumables: Vec::from_iter(
+                x.non_consumables
+                    .into_iter()
+                    .map(|x| NonConsumable { storage_slot: x.storage_slot, crafting_grid_slot: x.crafting_grid_slot }),
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CraftyTurtleFile {
+    client: String,
+    accesses: Vec<CraftyAccessFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemOutputFile {
+    item: FilterFile,
+    n_wanted: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FluidOutputFile {
+    fluid: String,
+    n_wanted: i64,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum RedstoneConditionFile {
+    High,
+    Low,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "snake_case")]
+enum StorageFile {
+    Chest {
+        accesses: Vec<BusAccessFile>,
+        #[serde(default)]
+        max_stack_size: Option<i32>,
+        #[serde(default)]
+        route: RouteFile,
+    },
+    Drawer {
+        accesses: Vec<BusAccessFile>,
+        filters: Vec<FilterFile>,
+        #[serde(default)]
+        route: RouteFile,
+    },
+    Bridge {
+        accesses: Vec<BridgeAccessFile>,
+        #[serde(default)]
+        route: RouteFile,
+    },
+    Trash {
+        accesses: Vec<BusAccessFile>,
+        route: TrashRouteFile,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RouteFile {
+    #[serde(default)]
+    allow: Vec<FilterFile>,
+    #[serde(default)]
+    deny: Vec<FilterFile>,
+    #[serde(default)]
+    tier: i32,
+    #[serde(default)]
+    overflow: bool,
+    #[serde(default)]
+    no_nbt: bool,
+    #[serde(default)]
+    above: Option<i32>,
+}
+
+impl From<RouteFile> for Route {
+    fn from(x: RouteFile) -> Self {
+        Route {
+            allow: filters(x.allow),
+            deny: filters(x.deny),
+            tier: x.tier,
+            overflow: x.overflow,
+            no_nbt: x.no_nbt,
+            above: x.above,
+        }
+    }
+}
+
+// A trash with a route allowing everything would void whatever the other storages don't take.
+#[derive(Deserialize)]
+#[serde(try_from = "RouteFile")]
+struct TrashRouteFile(RouteFile);
+
+impl TryFrom<RouteFile> for TrashRouteFile {
+    type Error = LocalStr;
+    fn try_from(x: RouteFile) -> Result<Self, LocalStr> {
+        if x.allow.is_empty() && x.above.is_none() {
+            return Err(local_str!("trash route needs a non-empty allow or above"));
+        }
+        Ok(TrashRouteFile(x))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FluidStorageFile {
+    accesses: Vec<TankAccessFile>,
+    fluid: String,
+    capacity: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrderOutputFile {
+    name: String,
+    accesses: Vec<BusAccessFile>,
+    tokens: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "snake_case")]
+enum ProcessFile {
+    ManualUi {
+        #[serde(default)]
+        accesses: Vec<BusAccessFile>,
+    },
+    Buffered {
+        name: String,
+        accesses: Vec<BusAccessFile>,
+        #[serde(default)]
+        slot_filter: Option<SlotFilterFile>,
+        #[serde(default)]
+        to_extract: Option<ExtractFile>,
+        #[serde(default)]
+        recipes: Vec<BufferedRecipeFile>,
+        max_recipe_inputs: i32,
+        #[serde(default)]
+        stocks: Vec<InputFile<Size>>,
+    },
+    Slotted {
+        name: String,
+        accesses: Vec<BusAccessFile>,
+        input_slots: Vec<usize>,
+        #[serde(default)]
+        to_extract: Option<ExtractFile>,
+        recipes: Vec<SlottedRecipeFile>,
+        #[serde(default)]
+        strict_priority: bool,
+    },
+    MultiInvSlotted {
+        name: String,
+        input_slots: Vec<Vec<usize>>,
+        accesses: Vec<MultiInvAccessFile>,
+        #[serde(default)]
+        to_extract: Option<ExtractFile>,
+        recipes: Vec<MultiInvSlottedRecipeFile>,
+        #[serde(default)]
+        strict_priority: bool,
+    },
+    FluidSlotted {
+        name: String,
+        input_slots: Vec<Vec<usize>>,
+        input_tanks: Vec<Vec<usize>>,
+        accesses: Vec<InvTankAccessFile>,
+        #[serde(default)]
+        to_extract: Option<ExtractFile>,
+        #[serde(default)]
+        fluid_extract: Option<FluidExtractFile>,
+        recipes: Vec<FluidSlottedRecipeFile>,
+        #[serde(default)]
+        strict_priority: bool,
+    },
+    Scattering {
+        name: String,
+        accesses: Vec<BusAccessFile>,
+        input_slots: Vec<usize>,
+        #[serde(default)]
+        to_extract: Option<ExtractFile>,
+        recipes: Vec<ScatteringRecipeFile>,
+        max_per_slot: i32,
+    },
+    Crafty {
+        name: String,
+        turtles: Vec<CraftyTurtleFile>,
+        recipes: Vec<CraftingGridRecipeFile>,
+    },
+    Workbench {
+        name: String,
+        accesses: Vec<BusAccessFile>,
+        recipes: Vec<CraftingGridRecipeFile>,
+    },
+    BlockingOutput {
+        accesses: Vec<BusAccessFile>,
+        #[serde(default)]
+        slot_filter: Option<SlotFilterFile>,
+        outputs: Vec<ItemOutputFile>,
+    },
+    BlockingFluidOutput {
+        accesses: Vec<TankAccessFile>,
+        outputs: Vec<FluidOutputFile>,
+    },
+    SyncAndRestock {
+        name: String,
+        accesses: Vec<BusAccessFile>,
+        accesses_in: Vec<RedstoneAccessFile>,
+        accesses_out: Vec<RedstoneAccessFile>,
+        stocks: Vec<InputFile<Size>>,
+        #[serde(default)]
+        hold_if_unfilled: bool,
+    },
+    ItemCycle {
+        name: String,
+        file_name: String,
+        accesses: Vec<BusAccessFile>,
+        slot: usize,
+        items: Vec<InputFile<NoSpec>>,
+    },
+    RedstoneEmitter {
+        name: String,
+        accesses: Vec<RedstoneAccessFile>,
+        off: u8,
+        on: u8,
+        outputs: OutputsFile,
+    },
+    LowAlert {
+        item: FilterFile,
+        n_wanted: i32,
+    },
+    FluidLowAlert {
+        fluid: String,
+        n_wanted: i64,
+    },
+    // Runs `child` only while `outputs` wants something.
+    Conditional {
+        outputs: OutputsFile,
+        child: Box<ProcessFile>,
+    },
+    RedstoneConditional {
+        #[serde(default)]
+        name: Option<String>,
+        accesses: Vec<RedstoneAccessFile>,
+        condition: RedstoneConditionFile,
+        child: Box<ProcessFile>,
+    },
+}
+
+impl ProcessFile {
+    fn into_process(self, factory: &Factory) -> Rc<RefCell<dyn Process>> {
+        match self {
+            ProcessFile::ManualUi { accesses } => ManualUiConfig { accesses: v(accesses) }.into_process(factory),
+            ProcessFile::Buffered { name, accesses, slot_filter, to_extract, recipes, max_recipe_inputs, stocks } => {
+                BufferedConfig {
+                    name: s(name),
+                    accesses: v(accesses),
+                    slot_filter: slot_filter.map(SlotFilter::from),
+                    to_extract: to_extract.map(ExtractFilter::from),
+                    recipes: Vec::from_iter(recipes.into_iter().map(|x| BufferedRecipe {
+                        outputs: x.outputs.into(),
+                        inputs: Vec::from_iter(x.inputs.into_iter().map(buffered_input)),
+                        max_inputs: x.max_inputs,
+                    })),
+                    max_recipe_inputs,
+                    stocks: Vec::from_iter(stocks.into_iter().map(buffered_input)),
+                }
+                .into_process(factory)
+            }
+            ProcessFile::Slotted { name, accesses, input_slots, to_extract, recipes, strict_priority } => {
+                SlottedConfig {
+                    name: s(name),
+                    accesses: v(accesses),
+                    input_slots,
+                    to_extract: to_extract.map(ExtractFilter::from),
+                    recipes: Vec::from_iter(recipes.into_iter().map(|x| SlottedRecipe {
+                        outputs: x.outputs.into(),
+                        inputs: Vec::from_iter(
+                            x.inputs.into_iter().map(|x| x.into_input(|item, x| SlottedInput::new(item, x.slots))),
+                        ),
+                        max_sets: x.max_sets,
+                    })),
+                    strict_priority,
+                }
+                .into_process(factory)
+            }
+            ProcessFile::MultiInvSlotted { name, input_slots, accesses, to_extract, recipes, strict_priority } => {
+                MultiInvSlottedConfig {
+                    name: s(name),
+                    input_slots,
+                    accesses: v(accesses),
+                    to_extract: to_extract.map(MultiInvExtractFilter::from),
+                    recipes: Vec::from_iter(recipes.into_iter().map(|x| MultiInvSlottedRecipe {
+                        outputs: x.outputs.into(),
+                        inputs: Vec::from_iter(x.inputs.into_iter().map(multi_inv_slotted_input)),
+                        max_sets: x.max_sets,
+                    })),
+                    strict_priority,
+                }
+                .into_process(factory)
+            }
+            ProcessFile::FluidSlotted {
+                name,
+                input_slots,
+                input_tanks,
+                accesses,
+                to_extract,
+                fluid_extract,
+                recipes,
+                strict_priority,
+            } => FluidSlottedConfig {
+                name: s(name),
+                input_slots,
+                input_tanks,
+                accesses: v(accesses),
+                to_extract: to_extract.map(MultiInvExtractFilter::from),
+                fluid_extract: fluid_extract.and_then(Option::from),
+                recipes: Vec::from_iter(recipes.into_iter().map(|x| FluidSlottedRecipe {
+                    outputs: x.outputs.into(),
+                    inputs: Vec::from_iter(x.inputs.into_iter().map(multi_inv_slotted_input)),
+                    fluids: v(x.fluids),
+                    max_sets: x.max_sets,
+                })),
+                strict_priority,
+            }
+            .into_process(factory),
+            ProcessFile::Scattering { name, accesses, input_slots, to_extract, recipes, max_per_slot } => {
+                ScatteringConfig {
+                    name: s(name),
+                    accesses: v(accesses),
+                    input_slots,
+                    to_extract: to_extract.map(ExtractFilter::from),
+                    recipes: Vec::from_iter(recipes.into_iter().map(|x| {
+                        ScatteringRecipe::new(
+                            x.outputs.into(),
+                            x.input.into_input(|item, _| ScatteringInput::new(item)),
+                        )
+                    })),
+                    max_per_slot,
+                }
+                .into_process(factory)
+            }
+            ProcessFile::Crafty { name, turtles, recipes } => CraftyConfig {
+                name: s(name),
+                turtles: Vec::from_iter(
+                    turtles.into_iter().map(|x| CraftyTurtle { client: s(x.client), accesses: v(x.accesses) }),
+                ),
+                recipes: v(recipes),
+            }
+            .into_process(factory),
+            ProcessFile::Workbench { name, accesses, recipes } => {
+                WorkbenchConfig { name: s(name), accesses: v(accesses), recipes: v(recipes) }.into_process(factory)
+            }
+            ProcessFile::BlockingOutput { accesses, slot_filter, outputs } => BlockingOutputConfig {
+                accesses: v(accesses),
+                slot_filter: slot_filter.map(SlotFilter::from),
+                outputs: Vec::from_iter(outputs.into_iter().map(|x| Output { item: x.item.0, n_wanted: x.n_wanted })),
+            }
+            .into_process(factory),
+            ProcessFile::BlockingFluidOutput { accesses, outputs } => BlockingFluidOutputConfig {
+                accesses: v(accesses),
+                outputs: Vec::from_iter(
+                    outputs.into_iter().map(|x| FluidOutput { fluid: s(x.fluid), n_wanted: x.n_wanted }),
+                ),
+            }
+            .into_process(factory),
+            ProcessFile::SyncAndRestock { name, accesses, accesses_in, accesses_out, stocks, hold_if_unfilled } => {
+                let stocks = Vec::from_iter(stocks.into_iter().map(buffered_input));
+                SyncAndRestockConfig {
+                    name: s(name),
+                    accesses: v(accesses),
+                    accesses_in: v(accesses_in),
+                    accesses_out: v(accesses_out),
+                    stocks: Box::new(move |_| stocks.clone()),
+                    hold_if_unfilled,
+                }
+                .into_process(factory)
+            }
+            ProcessFile::ItemCycle { name, file_name, accesses, slot, items } => ItemCycleConfig {
+                name: s(name),
+                file_name: s(file_name),
+                accesses: v(accesses),
+                slot,
+                items: Vec::from_iter(items.into_iter().map(|x| x.into_input(|item, _| ScatteringInput::new(item)))),
+            }
+            .into_process(factory),
+            ProcessFile::RedstoneEmitter { name, accesses, off, on, outputs } => {
+                let outputs = Rc::<dyn Outputs>::from(outputs);
+                let outputs = Box::new(move |factory: &Factory| outputs.get_priority(factory));
+                RedstoneEmitterConfig { accesses: v(accesses), output: emit_when_want_item(s(name), off, on, outputs) }
+                    .into_process(factory)
+            }
+            ProcessFile::LowAlert { item, n_wanted } => LowAlert::new(item.0, n_wanted).into_process(factory),
+            ProcessFile::FluidLowAlert { fluid, n_wanted } => FluidLowAlert(s(fluid), n_wanted).into_process(factory),
+            ProcessFile::Conditional { outputs, child } => {
+                let outputs = Rc::<dyn Outputs>::from(outputs);
+                ConditionalConfig {
+                    condition: Box::new(move |factory| outputs.get_priority(factory).is_some()),
+                    child: child.into_process(factory),
+                }
+                .into_process(factory)
+            }
+            ProcessFile::RedstoneConditional { name, accesses, condition, child } => RedstoneConditionalConfig {
+                name: name.map(s),
+                accesses: v(accesses),
+                condition: Box::new(move |value| match condition {
+                    RedstoneConditionFile::High => value > 0,
+                    RedstoneConditionFile::Low => value == 0,
+                }),
+                child: child.into_process(factory),
+            }
+            .into_process(factory),
+        }
+    }
+}
+
+fn default_detail_cache() -> String { "detail_cache.txt".to_owned() }
+fn default_detail_cache_flush() -> Seconds { Seconds(Duration::from_secs(60)) }
+fn default_port() -> u16 { 1847 }
+fn default_min_cycle_time() -> Seconds { Seconds(Duration::from_secs(1)) }
+fn default_capacity_alerts() -> Vec<f64> { vec![90., 98.] }
+fn default_history_interval() -> Seconds { Seconds(Duration::from_secs(60)) }
+fn default_history_retention() -> Hours { Hours(Duration::from_secs(168 * 3600)) }
+
+#[derive(Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+struct HistoryFile {
+    file_name: String,
+    // In seconds.
+    #[serde(default = "default_history_interval")]
+    interval: Seconds,
+    // In hours.
+    #[serde(default = "default_history_retention")]
+    retention: Hours,
+}
+
+impl From<HistoryFile> for HistoryConfig {
+    fn from(x: HistoryFile) -> Self {
+        HistoryConfig { file_name: s(x.file_name), interval: x.interval.0, retention: x.retention.0 }
+    }
+}
+
+fn default_timeout() -> Seconds { Seconds(Duration::from_secs(30)) }
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum OnTimeout {
+    #[default]
+    Disconnect,
+    Fail,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ApiFile {
+    #[default]
+    Generic,
+    Plethora,
+}
+
+impl From<ApiFile> for Api {
+    fn from(x: ApiFile) -> Self {
+        match x {
+            ApiFile::Generic => Api::Generic,
+            ApiFile::Plethora => Api::Plethora,
+        }
+    }
+}
+
+// In seconds; `methods` is keyed by peripheral method (e.g. `list`) or `turtle.<func>`, `redstone.input`, ...
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimeoutsFile {
+    #[serde(default = "default_timeout")]
+    default: Seconds,
+    #[serde(default)]
+    methods: FnvHashMap<String, Seconds>,
+    // Only reads are retried.
+    #[serde(default)]
+    retries: usize,
+    #[serde(default)]
+    on_timeout: OnTimeout,
+}
+
+impl Default for TimeoutsFile {
+    fn default() -> Self {
+        TimeoutsFile {
+            default: default_timeout(),
+            methods: FnvHashMap::default(),
+            retries: 0,
+            on_timeout: OnTimeout::default(),
+        }
+    }
+}
+
+impl From<TimeoutsFile> for TimeoutConfig {
+    fn from(x: TimeoutsFile) -> Self {
+        TimeoutConfig {
+            default: x.default.0,
+            methods: x.methods.into_iter().map(|(method, t)| (s(method), t.0)).collect(),
+            n_retries: x.retries,
+            disconnect: matches!(x.on_timeout, OnTimeout::Disconnect),
+        }
+    }
+}
+
+// PEM files of the certificate chain and private key for `wss://`.
+#[derive(Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+struct TlsFile {
+    cert: String,
+    key: String,
+}
+
+// Fields that can't be changed by reloading.
+#[derive(PartialEq)]
+struct StartupFields {
+    port: u16,
+    tls: Option<TlsFile>,
+    http_port: Option<u16>,
+    detail_cache: String,
+    history: Option<HistoryFile>,
+    journal: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FactoryFile {
+    #[serde(default = "default_detail_cache")]
+    detail_cache: String,
+    // In seconds, see `DetailCache::set_flush_interval`.
+    #[serde(default = "default_detail_cache_flush")]
+    detail_cache_flush: Seconds,
+    // In hours since an item was last seen, see `DetailCache::set_retention`.
+    #[serde(default)]
+    detail_cache_retention: Option<Hours>,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default)]
+    tls: Option<TlsFile>,
+    #[serde(default)]
+    http_port: Option<u16>,
+    #[serde(default = "default_min_cycle_time")]
+    min_cycle_time: Seconds,
+    // In seconds, see `FactoryConfig::relist_interval`.
+    #[serde(default)]
+    relist_interval: Option<Seconds>,
+    // Percentages, see `FactoryConfig::capacity_alerts`.
+    #[serde(default = "default_capacity_alerts")]
+    capacity_alerts: Vec<f64>,
+    #[serde(default)]
+    log_clients: Vec<String>,
+    // Login name to shared secret, see `Server::set_secrets`.
+    #[serde(default)]
+    client_secrets: FnvHashMap<String, String>,
+    // See `Server::set_dry_run`.
+    #[serde(default)]
+    dry_run: bool,
+    // Per client, see `Server::set_max_in_flight`.
+    #[serde(default)]
+    max_in_flight: Option<usize>,
+    // See `Server::set_timeouts`.
+    #[serde(default)]
+    timeouts: TimeoutsFile,
+    // For clients that don't report theirs at login, see `Server::set_apis`.
+    #[serde(default)]
+    peripheral_api: ApiFile,
+    #[serde(default)]
+    client_apis: FnvHashMap<String, ApiFile>,
+    bus_accesses: Vec<BasicAccessFile>,
+    #[serde(default)]
+    fluid_bus_accesses: Vec<FluidAccessFile>,
+    #[serde(default)]
+    fluid_bus_capacity: i64,
+    #[serde(default)]
+    backups: Vec<(FilterFile, i32)>,
+    #[serde(default)]
+    fluid_backups: Vec<(String, i64)>,
+    #[serde(default)]
+    storages: Vec<StorageFile>,
+    #[serde(default)]
+    fluid_storages: Vec<FluidStorageFile>,
+    #[serde(default)]
+    processes: Vec<ProcessFile>,
+    #[serde(default)]
+    order_outputs: Vec<OrderOutputFile>,
+    #[serde(default)]
+    history: Option<HistoryFile>,
+    #[serde(default)]
+    journal: Option<String>,
+}
+
+fn parse(path: &str) -> Result<FactoryFile, LocalStr> {
+    let data = read_to_string(path).map_err(|e| local_fmt!("{}: {}", path, e))?;
+    serde_json::from_str(&data).map_err(|e| local_fmt!("{}: {}", path, e))
+}
+
+fn get_modified(path: &str) -> Option<SystemTime> { metadata(path).and_then(|x| x.modified()).ok() }
+
+impl FactoryFile {
+    fn get_startup_fields(&self) -> StartupFields {
+        StartupFields {
+            port: self.port,
+            tls: self.tls.clone(),
+            http_port: self.http_port,
+            detail_cache: self.detail_cache.clone(),
+            history: self.history.clone(),
+            journal: self.journal.clone(),
+        }
+    }
+
+    // Replaces everything except `tui`, `detail_cache` and `server`, which are kept across reloads.
+    fn apply(self, factory: &mut Factory) {
+        let config = &mut factory.config;
+        config.min_cycle_time = self.min_cycle_time.0;
+        config.relist_interval = self.relist_interval.map_or(Duration::ZERO, |x| x.0);
+        config.capacity_alerts = self.capacity_alerts;
+        config.log_clients = ss(self.log_clients);
+        config.bus_accesses = v(self.bus_accesses);
+        config.fluid_bus_accesses = v(self.fluid_bus_accesses);
+        config.fluid_bus_capacity = self.fluid_bus_capacity;
+        config.backups = Vec::from_iter(self.backups.into_iter().map(|(filter, n)| (filter.0, n)));
+        config.fluid_backups = Vec::from_iter(self.fluid_backups.into_iter().map(|(fluid, n)| (s(fluid), n)));
+        let secrets = self.client_secrets.into_iter().map(|(login, secret)| (s(login), s(secret)));
+        config.server.borrow_mut().set_secrets(secrets.collect());
+        config.server.borrow_mut().set_dry_run(self.dry_run);
+        config.server.borrow_mut().set_max_in_flight(self.max_in_flight);
+        config.server.borrow_mut().set_timeouts(self.timeouts.into());
+        config.detail_cache.borrow_mut().set_flush_interval(self.detail_cache_flush.0);
+        let retention = self.detail_cache_retention.map(|x| x.0);
+        config.detail_cache.borrow_mut().set_retention(retention);
+        let apis = self.client_apis.into_iter().map(|(login, api)| (s(login), api.into()));
+        config.server.borrow_mut().set_apis(self.peripheral_api.into(), apis.collect());
+        for storage in self.storages {
+            match storage {
+                StorageFile::Chest { accesses, max_stack_size, route } => factory.add_routed_storage(
+                    ChestConfig {
+                        accesses: v(accesses),
+                        override_max_stack_size: max_stack_size.map(|n| Box::new(move |_| n) as _),
+                    },
+                    route.into(),
+                ),
+                StorageFile::Drawer { accesses, filters: x, route } => factory
+                    .add_routed_storage(DrawerConfig { accesses: v(accesses), filters: filters(x) }, route.into()),
+                StorageFile::Bridge { accesses, route } => {
+                    factory.add_routed_storage(BridgeConfig { accesses: v(accesses) }, route.into())
+                }
+                StorageFile::Trash { accesses, route } => {
+                    factory.add_routed_storage(TrashConfig { accesses: v(accesses) }, route.0.into())
+                }
+            }
+        }
+        for storage in self.fluid_storages {
+            factory.add_fluid_storage(FluidStorageConfig {
+                accesses: v(storage.accesses),
+                fluid: s(storage.fluid),
+                capacity: storage.capacity,
+            })
+        }
+        for process in self.processes {
+            let process = process.into_process(factory);
+            factory.add_process(process)
+        }
+        for output in self.order_outputs {
+            factory.add_order_output(OrderOutputConfig {
+                name: s(output.name),
+                accesses: v(output.accesses),
+                tokens: ss(output.tokens),
+            })
+        }
+    }
+}
+
+// Reloads on the `reload` command or when the file's modification time changes.
+struct FileReloader {
+    path: String,
+    modified: Option<SystemTime>,
+    startup: StartupFields,
+}
+
+impl Reloader for FileReloader {
+    fn is_outdated(&mut self) -> bool {
+        let modified = get_modified(&self.path);
+        modified != replace(&mut self.modified, modified)
+    }
+
+    fn reload(&mut self, factory: &mut Factory) -> Result<(), LocalStr> {
+        // Also when requested by `reload`, so that the same edit isn't loaded again by `is_outdated`.
+        self.modified = get_modified(&self.path);
+        let file = parse(&self.path)?;
+        if file.get_startup_fields() != self.startup {
+            return Err(local_str!(
+                "changing port, tls, http_port, detail_cache, history or journal requires a restart"
+            ));
+        }
+        file.apply(factory);
+        Ok(())
+    }
+}
+
+pub fn build_factory(tui: Rc<Tui>, path: &str) -> Option<Rc<RefCell<Factory>>> {
+    let modified = get_modified(path);
+    let file = parse(path).and_then(|file| {
+        let tls = file.tls.as_ref().map(|x| load_tls(&x.cert, &x.key)).transpose()?;
+        Ok((file, tls))
+    });
+    let (file, tls) = match file {
+        Ok(file) => file,
+        Err(e) => {
+            tui.log(format!("config not loaded: {e}"), 14);
+            return None;
+        }
+    };
+    let reloader = FileReloader { path: path.to_owned(), modified, startup: file.get_startup_fields() };
+    let config = FactoryConfig {
+        tui: tui.clone(),
+        detail_cache: DetailCache::new(&tui, s(file.detail_cache.clone())),
+        server: Server::new(tui, file.port, tls),
+        min_cycle_time: Duration::ZERO,
+        relist_interval: Duration::ZERO,
+        capacity_alerts: Vec::new(),
+        log_clients: Vec::new(),
+        bus_accesses: Vec::new(),
+        fluid_bus_accesses: Vec::new(),
+        fluid_bus_capacity: 0,
+        backups: Vec::new(),
+        fluid_backups: Vec::new(),
+        history: file.history.clone().map(HistoryConfig::from),
+        http_port: file.http_port,
+        journal: file.journal.clone().map(s),
+    };
+    Some(config.build(|factory| {
+        file.apply(factory);
+        factory.set_reloader(reloader)
+    }))
+}
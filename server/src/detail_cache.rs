@@ -1,5 +1,5 @@
-use crate::item::{Detail, Item};
-use crate::lua_value::{serialize, Parser, Table};
+use crate::item::{Detail, Filter, Item};
+use crate::lua_value::{serialize, table_remove, Parser, Table, Value};
 use crate::util::{make_local_one_shot, spawn, LocalReceiver, LocalSender};
 use crate::Tui;
 use abort_on_drop::ChildTask;
@@ -10,10 +10,19 @@ use std::{
     collections::hash_map::Entry,
     mem::take,
     rc::{Rc, Weak},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::time::sleep;
+
+// The file starts with this number, followed by `{ t = last seen, d = detail and item }` per entry.
+// Files without it are a plain sequence of details.
+const VERSION: u8 = 2;
+
+fn now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs()) }
 
 enum DetailState {
-    Resolved(Rc<Detail>),
+    // With the time it was last queried or inserted.
+    Resolved(Rc<Detail>, u64),
     Resolving { _resolver: ChildTask<()>, wait_queue: Vec<LocalSender<Rc<Detail>>> },
 }
 
@@ -23,9 +32,14 @@ pub enum DetailResult<'a> {
 }
 
 pub struct DetailCache {
+    tui: Rc<Tui>,
     path: LocalStr,
     state: FnvHashMap<Rc<Item>, DetailState>,
     weak: Weak<RefCell<DetailCache>>,
+    flush_interval: Duration,
+    // Entries not seen for this long are dropped at the next flush.
+    retention: Option<Duration>,
+    _flusher: Option<ChildTask<()>>,
 }
 
 async fn resolver_main(
@@ -60,18 +74,47 @@ async fn resolver_main(
 fn load(path: &str) -> Result<FnvHashMap<Rc<Item>, DetailState>, LocalStr> {
     let data = std::fs::read(path).map_err(|e| local_fmt!("{}", e))?;
     let mut result = FnvHashMap::default();
+    let mut version = None;
     Parser::new().shift(&data, &mut |value| {
-        let mut table = Table::try_from(value)?;
-        let item = Item::parse_part(&mut table)?;
+        let (mut table, last_seen) = match (version, value) {
+            (None, x @ Value::F(_)) => {
+                let x = u8::try_from(x)?;
+                if x != VERSION {
+                    return Err(local_fmt!("unsupported version {}", x));
+                }
+                version = Some(VERSION);
+                return Ok(());
+            }
+            (None | Some(1), value) => {
+                version = Some(1);
+                (Table::try_from(value)?, now())
+            }
+            (Some(_), value) => {
+                let mut entry = Table::try_from(value)?;
+                (table_remove(&mut entry, "d")?, table_remove::<i64>(&mut entry, "t")? as u64)
+            }
+        };
+        let item = Item::decode(&mut table)?;
         let detail = Detail::parse(table)?;
-        result.insert(item, DetailState::Resolved(detail));
+        result.insert(item, DetailState::Resolved(detail, last_seen));
         Ok(())
     })?;
     Ok(result)
 }
 
+async fn flusher_main(weak: Weak<RefCell<DetailCache>>) {
+    while let Some(interval) = weak.upgrade().map(|x| x.borrow().flush_interval) {
+        sleep(interval).await;
+        let Some(this) = weak.upgrade() else { break };
+        let mut this = this.borrow_mut();
+        if let Err(e) = this.flush() {
+            this.tui.log(format!("failed to save detail_cache: {}", e), 14)
+        }
+    }
+}
+
 impl DetailCache {
-    pub fn new(tui: &Tui, path: LocalStr) -> Rc<RefCell<Self>> {
+    pub fn new(tui: &Rc<Tui>, path: LocalStr) -> Rc<RefCell<Self>> {
         let state = match load(&*path) {
             Ok(state) => {
                 tui.log(format!("detail_cache loaded with {} entries", state.len()), 0);
@@ -82,7 +125,57 @@ impl DetailCache {
                 FnvHashMap::default()
             }
         };
-        Rc::new_cyclic(|weak| RefCell::new(Self { path, state, weak: weak.clone() }))
+        let result = Rc::new_cyclic(|weak| {
+            RefCell::new(Self {
+                tui: tui.clone(),
+                path,
+                state,
+                weak: weak.clone(),
+                flush_interval: Duration::from_secs(60),
+                retention: None,
+                _flusher: None,
+            })
+        });
+        result.borrow_mut()._flusher = Some(spawn(flusher_main(Rc::downgrade(&result))));
+        result
+    }
+
+    // Takes effect after the next flush.
+    pub fn set_flush_interval(&mut self, interval: Duration) {
+        self.flush_interval = interval.max(Duration::from_secs(1))
+    }
+    pub fn set_retention(&mut self, retention: Option<Duration>) { self.retention = retention }
+
+    // Drops entries matching the filter, so they are fetched again the next time they are seen.
+    pub fn invalidate(&mut self, filter: &Filter) -> usize {
+        let n = self.state.len();
+        self.state
+            .retain(|item, state| !matches!(state, DetailState::Resolved(detail, _) if filter.apply(item, detail)));
+        n - self.state.len()
+    }
+
+    // Evicts expired entries, then replaces the file atomically.
+    fn flush(&mut self) -> Result<usize, LocalStr> {
+        if let Some(retention) = self.retention {
+            let deadline = now().saturating_sub(retention.as_secs());
+            self.state.retain(|_, state| !matches!(state, DetailState::Resolved(_, last_seen) if *last_seen < deadline))
+        }
+        let mut data = Vec::new();
+        serialize(&VERSION.into(), &mut data);
+        let mut n = 0;
+        for (item, state) in &self.state {
+            if let DetailState::Resolved(detail, last_seen) = state {
+                let mut table = detail.encode();
+                item.encode(&mut table);
+                let entry = Table::from([("t".into(), (*last_seen as i64).into()), ("d".into(), table.into())]);
+                serialize(&entry.into(), &mut data);
+                n += 1
+            }
+        }
+        let tmp = format!("{}.tmp", self.path);
+        std::fs::write(&tmp, data).map_err(|e| local_fmt!("{}: {}", tmp, e))?;
+        std::fs::rename(&tmp, &*self.path).map_err(|e| local_fmt!("{}: {}", self.path, e))?;
+        Ok(n)
     }
 
     pub fn query(&mut self, item: &Rc<Item>) -> DetailResult {
@@ -97,7 +190,10 @@ impl DetailCache {
                 DetailResult::Resolving { sender: Some(sender), receiver: receiver }
             }
             Entry::Occupied(state) => match state.into_mut() {
-                DetailState::Resolved(x) => DetailResult::Resolved(x),
+                DetailState::Resolved(x, last_seen) => {
+                    *last_seen = now();
+                    DetailResult::Resolved(x)
+                }
                 DetailState::Resolving { wait_queue, .. } => {
                     let (sender, receiver) = make_local_one_shot();
                     wait_queue.push(sender);
@@ -107,17 +203,28 @@ impl DetailCache {
         }
     }
 
+    // For storages that report details along with their contents; a cached detail takes precedence.
+    pub fn query_or_insert(&mut self, item: &Rc<Item>, detail: impl FnOnce() -> Rc<Detail>) -> Rc<Detail> {
+        if let Some(DetailState::Resolved(x, last_seen)) = self.state.get_mut(item) {
+            *last_seen = now();
+            return x.clone();
+        }
+        let detail = detail();
+        self.insert(item.clone(), detail.clone());
+        detail
+    }
+
     fn insert(&mut self, item: Rc<Item>, detail: Rc<Detail>) {
         match self.state.entry(item) {
             Entry::Vacant(state) => {
-                state.insert(DetailState::Resolved(detail));
+                state.insert(DetailState::Resolved(detail, now()));
             }
             Entry::Occupied(mut state) => {
                 if let DetailState::Resolving { wait_queue, .. } = state.get_mut() {
                     for sender in take(wait_queue) {
                         sender.send(Ok(detail.clone()))
                     }
-                    state.insert(DetailState::Resolved(detail));
+                    state.insert(DetailState::Resolved(detail, now()));
                 }
             }
         }
@@ -126,18 +233,9 @@ impl DetailCache {
 
 impl Drop for DetailCache {
     fn drop(&mut self) {
-        let mut data = Vec::new();
-        for (item, detail) in &self.state {
-            if let DetailState::Resolved(detail) = detail {
-                let mut table = detail.encode();
-                item.encode(&mut table);
-                serialize(&table.into(), &mut data);
-            }
-        }
-        if let Err(e) = std::fs::write(&*self.path, data) {
-            println!("failed to save detail_cache: {}", e)
-        } else {
-            println!("saved detail_cache with {} entries", self.state.len());
+        match self.flush() {
+            Ok(n) => println!("saved detail_cache with {} entries", n),
+            Err(e) => println!("failed to save detail_cache: {}", e),
         }
     }
 }
@@ -1,18 +1,24 @@
-use crate::access::{BasicAccess, FluidAccess, GetClient, TankAccess};
-use crate::action::{ActionFuture, Call, Log};
+use crate::access::{BasicAccess, ExpectedPeripherals, FluidAccess, GetClient, TankAccess};
+use crate::action::{ActionFuture, Call, ListPeripherals, Log};
 use crate::detail_cache::DetailCache;
+use crate::history::{item_key, History, HistoryConfig};
+use crate::http_api::http_main;
 use crate::inventory::{list_inventory, Inventory};
-use crate::item::{Detail, DetailStack, Filter, Item};
-use crate::lua_value::{call_result, table_remove, try_into_integer, Key, Table};
-use crate::process::{IntoProcess, Process};
-use crate::storage::{DepositResult, Extractor, IntoStorage, Provider, Storage};
+use crate::item::{parse_query, Detail, DetailStack, Filter, Item};
+use crate::journal::{Journal, Undo};
+use crate::lua_value::{call_result, table_remove, try_into_integer, Key, Table, Value};
+use crate::planner::{parse_craft_command, PlanResult, Planner};
+use crate::process::{IntoProcess, Order, OrderOutputConfig, OrderOutputProcess, Process};
+use crate::recipe::{Outputs, PlanRecipe};
+use crate::storage::{Capacity, DepositResult, Extractor, IntoStorage, Provider, Rank, Route, Storage};
 use crate::util::{alive, join_outputs, join_tasks, make_local_one_shot, spawn, LocalReceiver, LocalSender};
-use crate::{server::Server, Tui};
+use crate::{get_command_args, server::Server, Tui};
 use abort_on_drop::ChildTask;
 use flexstr::{local_fmt, local_str, LocalStr};
 use fnv::{FnvHashMap, FnvHashSet};
+use regex::Regex;
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     cmp::{max, min},
     collections::{hash_map::Entry, BTreeMap, BinaryHeap, VecDeque},
     future::Future,
@@ -114,12 +120,54 @@ pub struct FactoryConfig {
     pub detail_cache: Rc<RefCell<DetailCache>>,
     pub server: Rc<RefCell<Server>>,
     pub min_cycle_time: Duration,
+    // How often chests and drawers are listed again; in between, they only track the items the server moved.
+    pub relist_interval: Duration,
+    // Percentages of storage slots used at which an alert is logged, once per crossing.
+    pub capacity_alerts: Vec<f64>,
     pub log_clients: Vec<LocalStr>,
     pub bus_accesses: Vec<BasicAccess>,
     pub fluid_bus_accesses: Vec<FluidAccess>,
     pub fluid_bus_capacity: i64,
     pub backups: Vec<(Filter, i32)>,
     pub fluid_backups: Vec<(LocalStr, i64)>,
+    pub history: Option<HistoryConfig>,
+    pub http_port: Option<u16>,
+    // File name of the journal of in-flight crafting jobs, see `Journal`.
+    pub journal: Option<LocalStr>,
+}
+
+// Rebuilds storages, processes and config at a cycle boundary, keeping the same server and detail cache.
+pub trait Reloader {
+    fn is_outdated(&mut self) -> bool { false }
+    // Called with storages and processes removed. On error, the factory restores the old ones.
+    fn reload(&mut self, factory: &mut Factory) -> Result<(), LocalStr>;
+}
+
+const COMMANDS: [&str; 4] = ["reload", "craft", "rate", "invalidate"];
+
+pub struct Plan {
+    pub target: Filter,
+    pub n_wanted: i32,
+    pub result: PlanResult,
+    pub is_done: bool,
+    // Sets of each product not yet started, as first planned. Replanning only sees stored items, not those in
+    // machines, so it can't tell how many are still needed by itself.
+    budgets: Vec<(Filter, Rc<Cell<i32>>)>,
+}
+
+// A product the plans need this cycle.
+struct Planned {
+    product: Filter,
+    budget: Rc<Cell<i32>>,
+    // At most the budget, less if the latest plan needs fewer.
+    n_sets: Cell<i32>,
+}
+
+// Storage contents as of the latest update, kept across the end of the cycle for the HTTP API.
+#[derive(Default)]
+pub struct Snapshot {
+    pub items: Vec<DetailStack>,
+    pub fluids: Vec<(LocalStr, i64)>,
 }
 
 pub struct FluidStorageConfig {
@@ -140,9 +188,20 @@ pub struct Factory {
     weak: Weak<RefCell<Factory>>,
     _task: ChildTask<Result<(), LocalStr>>,
     pub config: FactoryConfig,
-    storages: Vec<Rc<RefCell<dyn Storage>>>,
+    storages: Vec<(Rc<RefCell<dyn Storage>>, Route)>,
     processes: Vec<Rc<RefCell<dyn Process>>>,
     fluid_storages: Vec<Rc<RefCell<FluidStorage>>>,
+    reloader: Option<Box<dyn Reloader>>,
+    pub order_outputs: Vec<Rc<RefCell<OrderOutputProcess>>>,
+    n_orders: usize,
+    pub plans: Vec<Plan>,
+    planned: Vec<Planned>,
+    history: Option<RefCell<History>>,
+    journal: Option<RefCell<Journal>>,
+    pub snapshot: Snapshot,
+    _http: Option<ChildTask<()>>,
+    // By client, see `check_peripherals`.
+    peripheral_checks: FnvHashMap<LocalStr, ChildTask<()>>,
 
     pub items: FnvHashMap<Rc<Item>, RefCell<ItemInfo>>,
     label_map: FnvHashMap<LocalStr, Vec<Rc<Item>>>,
@@ -155,6 +214,10 @@ pub struct Factory {
     bus_free_queue: Vec<usize>,
     bus_size: Option<usize>,
     n_bus_updates: usize,
+    // Items on the bus that no storage took at the last bus update.
+    bus_stuck: Vec<LocalStr>,
+    // Number of `capacity_alerts` reached.
+    capacity_alert_level: usize,
 
     fluid_bus_task: Option<ChildTask<Result<(), LocalStr>>>,
     fluid_bus_allocations: FnvHashSet<usize>,
@@ -165,11 +228,11 @@ pub struct Factory {
 
 impl FactoryConfig {
     pub fn build(self, builder: impl FnOnce(&mut Factory)) -> Rc<RefCell<Factory>> {
-        let mut fluid_backups = FnvHashMap::default();
-        for (fluid, qty) in &self.fluid_backups {
-            *fluid_backups.entry(fluid.clone()).or_default() += qty
-        }
+        let fluid_backups = self.get_fluid_backup_map();
+        let history = self.history.as_ref().map(|x| RefCell::new(History::new(&self.tui, x)));
+        let journal = self.journal.clone().map(|x| RefCell::new(Journal::new(&self.tui, x)));
         Rc::new_cyclic(|weak| {
+            let http = self.http_port.map(|port| spawn(http_main(self.tui.clone(), weak.clone(), port)));
             let mut factory = Factory {
                 weak: weak.clone(),
                 _task: spawn(factory_main(weak.clone())),
@@ -177,6 +240,16 @@ impl FactoryConfig {
                 storages: Vec::new(),
                 processes: Vec::new(),
                 fluid_storages: Vec::new(),
+                reloader: None,
+                order_outputs: Vec::new(),
+                n_orders: 0,
+                plans: Vec::new(),
+                planned: Vec::new(),
+                history,
+                journal,
+                snapshot: Snapshot::default(),
+                _http: http,
+                peripheral_checks: FnvHashMap::default(),
 
                 items: FnvHashMap::default(),
                 label_map: FnvHashMap::default(),
@@ -189,6 +262,8 @@ impl FactoryConfig {
                 bus_free_queue: Vec::new(),
                 bus_size: None,
                 n_bus_updates: 0,
+                bus_stuck: Vec::new(),
+                capacity_alert_level: 0,
 
                 fluid_bus_task: None,
                 fluid_bus_allocations: FnvHashSet::default(),
@@ -197,11 +272,31 @@ impl FactoryConfig {
                 n_fluid_bus_updates: 0,
             };
             builder(&mut factory);
+            for client in factory.config.server.borrow().get_logins() {
+                factory.peripheral_checks.insert(client.clone(), spawn(check_peripherals(weak.clone(), client)));
+            }
+            let on_login = weak.clone();
+            factory.config.server.borrow_mut().set_on_login(Box::new(move |client| {
+                if let Some(factory) = on_login.upgrade() {
+                    let task = spawn(check_peripherals(on_login.clone(), client.clone()));
+                    factory.borrow_mut().peripheral_checks.insert(client.clone(), task);
+                }
+            }));
             RefCell::new(factory)
         })
     }
 }
 
+impl FactoryConfig {
+    fn get_fluid_backup_map(&self) -> FnvHashMap<LocalStr, i64> {
+        let mut result = FnvHashMap::default();
+        for (fluid, qty) in &self.fluid_backups {
+            *result.entry(fluid.clone()).or_default() += qty
+        }
+        result
+    }
+}
+
 impl Inventory for Factory {
     type Access = BasicAccess;
     fn get_weak(&self) -> &Weak<RefCell<Self>> { &self.weak }
@@ -213,7 +308,10 @@ impl Inventory for Factory {
 }
 
 impl Factory {
-    pub fn add_storage(&mut self, storage: impl IntoStorage) { self.storages.push(storage.into_storage(self)) }
+    pub fn add_storage(&mut self, storage: impl IntoStorage) { self.add_routed_storage(storage, Route::default()) }
+    pub fn add_routed_storage(&mut self, storage: impl IntoStorage, route: Route) {
+        self.storages.push((storage.into_storage(self), route))
+    }
     pub fn add_process(&mut self, process: impl IntoProcess) { self.processes.push(process.into_process(self)) }
     pub fn get_n_stored(&self, item: &Rc<Item>) -> i32 { self.items.get(item).map_or(0, |info| info.borrow().n_stored) }
     pub fn add_fluid_storage(&mut self, config: FluidStorageConfig) {
@@ -228,6 +326,348 @@ impl Factory {
         }))
     }
 
+    fn get_expected_peripherals(&self, client: LocalStr) -> ExpectedPeripherals {
+        let mut expected = ExpectedPeripherals::new(client);
+        expected.add("bus", &self.config.bus_accesses);
+        expected.add("fluid bus", &self.config.fluid_bus_accesses);
+        for (storage, _) in &self.storages {
+            storage.borrow().expect_peripherals(&mut expected)
+        }
+        for storage in &self.fluid_storages {
+            let storage = storage.borrow();
+            expected.add(&storage.config.fluid, &storage.config.accesses)
+        }
+        for process in &self.processes {
+            process.borrow().expect_peripherals(&mut expected)
+        }
+        expected
+    }
+
+    pub fn add_order_output(&mut self, config: OrderOutputConfig) {
+        let output = OrderOutputProcess::new(self, config);
+        self.processes.push(output.clone());
+        self.order_outputs.push(output)
+    }
+
+    pub fn get_order_output(&self, name: &str) -> Option<&Rc<RefCell<OrderOutputProcess>>> {
+        self.order_outputs.iter().find(|x| x.borrow().config.name == name)
+    }
+
+    // Queues delivery of `n_wanted` of `item` into `output`, returning the order id.
+    pub fn add_order(&mut self, output: &Rc<RefCell<OrderOutputProcess>>, item: Filter, n_wanted: i32) -> usize {
+        let id = self.n_orders;
+        self.n_orders += 1;
+        let mut output = output.borrow_mut();
+        let text = local_fmt!("order #{} to {}: {}*{}", id, output.config.name, item.describe(), n_wanted);
+        self.log(Log { text, color: 3 });
+        output.orders.push(Order { id, item, n_wanted, n_delivered: 0, n_in_flight: 0 });
+        id
+    }
+
+    pub fn set_reloader(&mut self, reloader: impl Reloader + 'static) { self.reloader = Some(Box::new(reloader)) }
+
+    fn check_reload(&mut self) {
+        let Some(mut reloader) = self.reloader.take() else { return };
+        let requested = !self.config.tui.take_commands("reload").is_empty();
+        if requested || reloader.is_outdated() {
+            let storages = take(&mut self.storages);
+            let processes = take(&mut self.processes);
+            let fluid_storages = take(&mut self.fluid_storages);
+            let order_outputs = take(&mut self.order_outputs);
+            if let Err(e) = reloader.reload(self) {
+                self.storages = storages;
+                self.processes = processes;
+                self.fluid_storages = fluid_storages;
+                self.order_outputs = order_outputs;
+                self.log(Log { text: local_fmt!("reload failed: {}", e), color: 14 })
+            } else {
+                self.fluid_backups = self.config.get_fluid_backup_map();
+                self.bus_size = None;
+                self.log(Log { text: local_str!("reloaded"), color: 13 });
+                self.move_orders(order_outputs);
+                // The new layout may expect different peripherals on clients that are already logged in.
+                for client in self.config.server.borrow().get_logins() {
+                    let task = spawn(check_peripherals(self.weak.clone(), client.clone()));
+                    self.peripheral_checks.insert(client, task);
+                }
+            }
+        }
+        self.reloader = Some(reloader)
+    }
+
+    // Pending orders follow their output across reloads by name.
+    fn move_orders(&self, old_outputs: Vec<Rc<RefCell<OrderOutputProcess>>>) {
+        for old in old_outputs {
+            let mut old = old.borrow_mut();
+            if let Some(new) = self.get_order_output(&old.config.name) {
+                new.borrow_mut().orders.append(&mut old.orders)
+            } else {
+                for order in &old.orders {
+                    let text = local_fmt!("order #{} dropped: {} was removed", order.id, old.config.name);
+                    self.log(Log { text, color: 14 })
+                }
+            }
+        }
+    }
+
+    // Requests `n` more of `target` on top of what is currently stored.
+    pub fn add_plan(&mut self, target: Filter, n: i32) {
+        let n_wanted = self.search_n_stored(&target).saturating_add(n);
+        let plan = Plan { target, n_wanted, result: PlanResult::default(), is_done: false, budgets: Vec::new() };
+        self.plans.push(plan)
+    }
+
+    // Sets of a recipe still needed by plans, not counting those already started.
+    pub fn get_plan_sets(&self, outputs: &dyn Outputs) -> i32 {
+        let products = outputs.get_products();
+        let planned = self.planned.iter().filter(|x| products.iter().any(|product| product.item == x.product));
+        planned.map(|x| x.n_sets.get()).sum()
+    }
+
+    // `None` once the sets needed by plans are all started.
+    pub fn get_plan_priority(&self, outputs: &dyn Outputs) -> Option<f64> {
+        let n_sets = self.get_plan_sets(outputs);
+        (n_sets > 0).then_some(n_sets as f64)
+    }
+
+    pub fn get_recipes(&self) -> Vec<PlanRecipe> {
+        Vec::from_iter(self.processes.iter().flat_map(|x| x.borrow().get_recipes()))
+    }
+
+    // Handles TUI commands that need up-to-date storage contents.
+    fn handle_commands(&mut self) {
+        let tui = self.config.tui.clone();
+        let crafts = tui.take_commands("craft");
+        if !crafts.is_empty() {
+            let recipes = self.get_recipes();
+            for args in crafts {
+                match parse_craft_command(self, &recipes, &args) {
+                    Ok((target, n)) => {
+                        self.log(Log { text: local_fmt!("craft: {}*{}", target.describe(), n), color: 3 });
+                        self.add_plan(target, n)
+                    }
+                    Err(e) => self.log(Log { text: local_fmt!("craft: {}", e), color: 14 }),
+                }
+            }
+        }
+        for args in tui.take_commands("rate") {
+            if let Err(e) = self.show_rates(&args) {
+                self.log(Log { text: local_fmt!("rate: {}", e), color: 14 })
+            }
+        }
+        for args in tui.take_commands("invalidate") {
+            if let Err(e) = self.invalidate_details(&args) {
+                self.log(Log { text: local_fmt!("invalidate: {}", e), color: 14 })
+            }
+        }
+        // Lines with '*' are left for the manual UI.
+        tui.take_input(|x| !x.contains('*') && !COMMANDS.iter().any(|name| get_command_args(x, name).is_some()));
+    }
+
+    fn record_history(&self, f: impl FnOnce(&mut History)) {
+        if let Some(history) = &self.history {
+            f(&mut history.borrow_mut())
+        }
+    }
+
+    // Returns the entry to pass to `journal_end` if the journal is enabled.
+    pub fn journal_begin(&self, process: &str, undo: Vec<Undo>) -> Option<usize> {
+        let journal = self.journal.as_ref()?;
+        match journal.borrow_mut().begin(process, undo) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                self.log(Log { text: local_fmt!("journal: {}", e), color: 14 });
+                None
+            }
+        }
+    }
+
+    pub fn journal_end<T>(&self, id: Option<usize>, result: &Result<T, LocalStr>) {
+        let (Some(journal), Some(id)) = (&self.journal, id) else { return };
+        if let Err(e) = journal.borrow_mut().end(id, result.is_ok()) {
+            self.log(Log { text: local_fmt!("journal: {}", e), color: 14 })
+        }
+    }
+
+    fn get_fluids(&self) -> FnvHashMap<LocalStr, i64> {
+        let mut result = FnvHashMap::<LocalStr, i64>::default();
+        for storage in &self.fluid_storages {
+            let storage = storage.borrow();
+            *result.entry(storage.config.fluid.clone()).or_default() += storage.n_stored_lo
+        }
+        result
+    }
+
+    fn update_snapshot(&mut self) {
+        if self.config.http_port.is_none() {
+            return;
+        }
+        let mut items = Vec::from_iter(self.items.iter().map(|(item, info)| {
+            let info = info.borrow();
+            DetailStack { item: item.clone(), detail: info.detail.clone(), size: info.n_stored }
+        }));
+        items.sort_by_key(|x| -x.size);
+        let mut fluids = Vec::from_iter(self.get_fluids());
+        fluids.sort_by_key(|x| -x.1);
+        self.snapshot = Snapshot { items, fluids }
+    }
+
+    fn sample_history(&self) {
+        let Some(history) = &self.history else { return };
+        let mut history = history.borrow_mut();
+        if !history.is_due() {
+            return;
+        }
+        let mut stored = Vec::from_iter(self.items.iter().map(|(item, info)| {
+            let info = info.borrow();
+            (('i', item_key(item)), info.detail.label.clone(), info.n_stored as i64)
+        }));
+        stored.extend(self.get_fluids().into_iter().map(|(fluid, qty)| (('f', fluid.clone()), fluid, qty)));
+        if let Err(e) = history.sample(stored) {
+            self.log(Log { text: local_fmt!("history: {}", e), color: 14 })
+        }
+    }
+
+    // `invalidate <label|=name>`: drops cached details of matching items, a regex matching the whole string.
+    // Items in storage are fetched again at the next cycle.
+    fn invalidate_details(&self, args: &str) -> Result<(), LocalStr> {
+        let args = args.trim();
+        if args.is_empty() {
+            return Err(local_str!("usage: invalidate <label|=name>"));
+        }
+        let (needle, prefix) = args.strip_prefix('=').map_or((args, ""), |x| (x, "="));
+        let filter = parse_query(&format!("{prefix}^(?:{needle})$"))?;
+        let n = self.config.detail_cache.borrow_mut().invalidate(&filter);
+        self.log(Log { text: local_fmt!("invalidate: {} entries dropped", n), color: 0 });
+        Ok(())
+    }
+
+    // `rate <hours> [label|=name]`: without a filter, shows the largest producers and drains.
+    fn show_rates(&self, args: &str) -> Result<(), LocalStr> {
+        let Some(history) = &self.history else { return Err(local_str!("history is not enabled")) };
+        let (hours, needle) = args.split_once(' ').unwrap_or((args, ""));
+        let window = (hours.parse::<f64>().ok().filter(|&x| x > 0.))
+            .and_then(|x| Duration::try_from_secs_f64(x * 3600.).ok())
+            .ok_or_else(|| local_str!("usage: rate <hours> [label|=name]"))?;
+        let (needle, by_name) = needle.strip_prefix('=').map_or((needle, false), |x| (x, true));
+        let regex = Regex::new(needle).map_err(|e| local_fmt!("invalid regex: {}", e))?;
+        let mut rates = history.borrow().get_rates(window, &regex, by_name);
+        if needle.is_empty() && rates.len() > 10 {
+            rates.drain(5..rates.len() - 5);
+        }
+        if rates.is_empty() {
+            self.log(Log { text: local_str!("rate: no changes"), color: 0 })
+        }
+        for x in rates {
+            let text = local_fmt!("{}: +{:.1}/h -{:.1}/h net {:+.1}/h", x.label, x.n_in, x.n_out, x.net);
+            self.log(Log { text, color: if x.net < 0. { 6 } else { 0 } })
+        }
+        Ok(())
+    }
+
+    fn update_plans(&mut self) {
+        self.planned.clear();
+        if self.plans.is_empty() {
+            return;
+        }
+        let recipes = self.get_recipes();
+        let mut plans = take(&mut self.plans);
+        let mut planner = Planner::new(self, &recipes);
+        let mut planned = Vec::new();
+        plans.retain_mut(|plan| {
+            let label = plan.target.describe();
+            if plan.is_done {
+                return false;
+            } else if self.search_n_stored(&plan.target) >= plan.n_wanted {
+                // Kept for one more cycle so that it can be shown as done.
+                self.log(Log { text: local_fmt!("plan done: {}*{}", label, plan.n_wanted), color: 13 });
+                plan.is_done = true;
+                plan.result = PlanResult::default();
+                return true;
+            }
+            let result = planner.plan(&plan.target, plan.n_wanted);
+            if result.overflow {
+                self.log(Log { text: local_fmt!("plan {}*{} dropped: too large", label, plan.n_wanted), color: 14 });
+                return false;
+            }
+            let describe = |x: &PlanResult| Vec::from_iter(x.missing.iter().map(|(x, n)| (x.describe(), *n)));
+            let missing = describe(&result);
+            if !missing.is_empty() && missing != describe(&plan.result) {
+                let missing = Vec::from_iter(missing.iter().map(|(x, n)| format!("{x}*{n}")));
+                self.log(Log { text: local_fmt!("plan {}: missing {}", label, missing.join(", ")), color: 6 })
+            }
+            let mut n_sets = Vec::<(Filter, i32)>::new();
+            for step in &result.steps {
+                match n_sets.iter_mut().find(|x| x.0 == step.product) {
+                    Some(x) => x.1 = x.1.saturating_add(step.n_sets),
+                    None => n_sets.push((step.product.clone(), step.n_sets)),
+                }
+            }
+            for (product, n_sets) in n_sets {
+                let budget = match plan.budgets.iter().find(|x| x.0 == product) {
+                    Some(x) => x.1.clone(),
+                    None => {
+                        let budget = Rc::new(Cell::new(n_sets));
+                        plan.budgets.push((product.clone(), budget.clone()));
+                        budget
+                    }
+                };
+                let n_sets = Cell::new(n_sets.min(budget.get()).max(0));
+                planned.push(Planned { product, budget, n_sets })
+            }
+            plan.result = result;
+            true
+        });
+        self.plans = plans;
+        self.planned = planned
+    }
+
+    // Lines of the dry-run report, next to the withheld actions logged by the server.
+    fn report(&self, text: std::fmt::Arguments) {
+        if self.config.server.borrow().is_dry_run() {
+            self.config.tui.log(format!("dry-run: {}", text), 10)
+        }
+    }
+
+    // Called when a process starts `n_sets` of a recipe, which counts against the plans needing its products.
+    pub fn report_demand(&self, process: &str, i_recipe: usize, outputs: &dyn Outputs, n_sets: i32) {
+        self.report(format_args!("{} chose recipe #{} x{}", process, i_recipe, n_sets));
+        for product in outputs.get_products() {
+            let mut n_remaining = n_sets;
+            for planned in self.planned.iter().filter(|x| x.product == product.item) {
+                let n = n_remaining.min(planned.n_sets.get());
+                planned.n_sets.set(planned.n_sets.get() - n);
+                planned.budget.set(planned.budget.get() - n);
+                n_remaining -= n
+            }
+        }
+    }
+
+    fn check_capacity(&mut self, capacity: Capacity) {
+        let used = capacity.used_fraction() * 100.;
+        let level = self.config.capacity_alerts.iter().filter(|&&x| used >= x).count();
+        if level > self.capacity_alert_level {
+            let text =
+                local_fmt!("storage almost full: {:.0}% of slots used, {} items fit", used, capacity.n_free_items);
+            self.log(Log { text, color: 14 })
+        } else if level == 0 && self.capacity_alert_level > 0 {
+            self.log(Log { text: local_fmt!("storage no longer almost full: {:.0}% of slots used", used), color: 13 })
+        }
+        self.capacity_alert_level = level
+    }
+
+    // Logs when the bus gets stuck with items no storage takes, or is no longer stuck.
+    fn set_bus_stuck(&mut self, stuck: Vec<LocalStr>) {
+        if stuck != self.bus_stuck {
+            if !stuck.is_empty() {
+                self.log(Log { text: local_fmt!("bus stuck, no storage takes: {}", stuck.join(", ")), color: 14 })
+            } else {
+                self.log(Log { text: local_str!("bus no longer stuck"), color: 13 })
+            }
+        }
+        self.bus_stuck = stuck
+    }
+
     pub fn log(&self, action: Log) {
         self.config.tui.log(action.text.to_std_string(), action.color);
         let server = self.config.server.borrow();
@@ -314,6 +754,7 @@ impl Factory {
 
     pub fn bus_free(&mut self, slot: usize) {
         if let Some(state) = self.bus_wait_queue.pop_front() {
+            self.report(format_args!("bus slot {} reallocated", slot));
             state.send(Ok(slot))
         } else {
             self.bus_allocations.remove(&slot);
@@ -335,14 +776,26 @@ impl Factory {
         }
     }
 
-    fn deposit_item(&self, bus_slot: usize, mut stack: DetailStack, tasks: &mut Vec<ChildTask<Result<(), LocalStr>>>) {
+    fn deposit_item(
+        &self,
+        bus_slot: usize,
+        mut stack: DetailStack,
+        tasks: &mut Vec<ChildTask<Result<(), LocalStr>>>,
+        stuck: &mut Vec<LocalStr>,
+    ) {
         self.log(Log { text: local_fmt!("{}*{}", stack.detail.label, stack.size), color: 1 });
+        self.record_history(|x| x.record_in('i', item_key(&stack.item), stack.size as _));
+        let n_stored = self.get_n_stored(&stack.item);
         while stack.size > 0 {
-            let mut best: Option<(&Rc<RefCell<dyn Storage>>, i32)> = None;
-            for storage in &self.storages {
+            let mut best: Option<(&Rc<RefCell<dyn Storage>>, Rank)> = None;
+            for (storage, route) in &self.storages {
+                if !route.allows(&stack.item, &stack.detail, n_stored) {
+                    continue;
+                }
                 let Some(prio) = storage.borrow_mut().deposit_priority(&stack.item, &stack.detail) else { continue };
-                if best.as_ref().map_or(true, |&(_, best)| prio > best) {
-                    best = Some((storage, prio))
+                let rank = route.rank(prio, storage.borrow().is_void());
+                if best.as_ref().map_or(true, |&(_, best)| rank > best) {
+                    best = Some((storage, rank))
                 }
             }
             if let Some((storage, _)) = best {
@@ -350,6 +803,7 @@ impl Factory {
                 stack.size -= n_deposited;
                 tasks.push(task)
             } else {
+                stuck.push(local_fmt!("{}*{}", stack.detail.label, stack.size));
                 tasks.push(spawn(async { Err(local_str!("storage is full")) }));
                 break;
             }
@@ -359,6 +813,7 @@ impl Factory {
     pub fn reserve_item(&self, reason: &str, item: &Rc<Item>, size: i32) -> Reservation {
         let mut info = self.items.get(item).unwrap().borrow_mut();
         self.log(Log { text: local_fmt!("{reason}: {}*{size}", info.detail.label,), color: 3 });
+        self.record_history(|x| x.record_out('i', item_key(item), size as _));
         info.reserve(size)
     }
 
@@ -392,6 +847,7 @@ impl Factory {
 
     pub fn fluid_bus_free(&mut self, bus: usize) {
         if let Some(state) = self.fluid_bus_wait_queue.pop_front() {
+            self.report(format_args!("fluid bus {} reallocated", bus));
             state.send(Ok(bus))
         } else {
             self.fluid_bus_allocations.remove(&bus);
@@ -421,6 +877,7 @@ impl Factory {
         tasks: &mut Vec<ChildTask<Result<(), LocalStr>>>,
     ) {
         self.log(Log { text: local_fmt!("{fluid}*{qty}"), color: 1 });
+        self.record_history(|x| x.record_in('f', fluid.clone(), qty));
         let server = self.get_server().borrow();
         while qty > 0 {
             let mut best: Option<(&Rc<RefCell<FluidStorage>>, i64)> = None;
@@ -459,6 +916,7 @@ impl Factory {
 
     pub fn reserve_fluid(&self, reason: &str, fluid: &str, mut qty: i64) -> FluidReservation {
         self.log(Log { text: local_fmt!("{reason}: {fluid}*{qty}",), color: 3 });
+        self.record_history(|x| x.record_out('f', fluid.into(), qty));
         let mut extractors = Vec::new();
         while qty > 0 {
             let mut best = None;
@@ -482,7 +940,7 @@ impl Factory {
     }
 
     fn end_of_cycle(&mut self) {
-        for storage in &self.storages {
+        for (storage, _) in &self.storages {
             storage.borrow_mut().cleanup()
         }
         for storage in &self.fluid_storages {
@@ -502,6 +960,7 @@ async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
         let cycle_start_time = Instant::now();
         {
             alive_mut!(factory, this);
+            this.check_reload();
             let text = if let Some(last) = cycle_start_last {
                 local_fmt!(
                     "OCRemote #{}, nBusUpdates={},{}, cycleTime={:.3}",
@@ -518,7 +977,15 @@ async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
             this.n_fluid_bus_updates = 0
         }
         let result = async {
+            recover_journal(&factory).await?;
             update_storages(&factory).await?;
+            {
+                alive_mut!(factory, this);
+                this.sample_history();
+                this.update_snapshot();
+                this.handle_commands();
+                this.update_plans()
+            }
             run_processes(&factory).await
         }
         .await;
@@ -553,20 +1020,101 @@ async fn factory_main(factory: Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
     }
 }
 
+async fn undo(factory: &Weak<RefCell<Factory>>, undo: &Undo) -> Result<i32, LocalStr> {
+    let s = |x: &String| Value::S(x.as_str().into());
+    let (client, call, bus_slot) = match undo {
+        Undo::Return { client, bus_addr, inv_addr, slot } => {
+            let bus_slot = alive(factory)?.borrow_mut().bus_allocate();
+            let bus_slot = bus_slot.await?;
+            let args = vec!["pullItems".into(), s(inv_addr), (slot + 1).into(), 64.into(), (bus_slot + 1).into()];
+            (client, Call { addr: bus_addr.as_str().into(), args }, Some(bus_slot))
+        }
+        Undo::Move { client, addr, method, other, from_slot, to_slot } => {
+            let args = vec![s(method), s(other), (from_slot + 1).into(), 64.into(), (to_slot + 1).into()];
+            (client, Call { addr: addr.as_str().into(), args }, None)
+        }
+    };
+    let action = ActionFuture::from(call);
+    alive(factory)?.borrow().config.server.borrow().enqueue_request_group(client, vec![action.clone().into()]);
+    let result = action.await;
+    alive_mut!(factory, this);
+    this.bus_deposit(bus_slot);
+    Ok(call_result(result?).unwrap_or(0))
+}
+
+// Undoes jobs left open by a previous run or by a failure, before any process runs again.
+async fn recover_journal(factory: &Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
+    let (entries, owners) = {
+        alive!(factory, this);
+        let entries = this.journal.as_ref().map_or_else(Vec::new, |x| x.borrow().get_pending());
+        (entries, this.get_expected_peripherals(local_str!("")).owners)
+    };
+    'entries: for entry in entries {
+        // Its peripherals may be gone or reused by something else since a reload.
+        if !owners.contains(&*entry.process) {
+            alive!(factory, this);
+            let text = local_fmt!("journal: dropped #{}, {} was removed", entry.id, entry.process);
+            this.log(Log { text, color: 14 });
+            let result = this.journal.as_ref().unwrap().borrow_mut().recovered(entry.id);
+            if let Err(e) = result {
+                this.log(Log { text: local_fmt!("journal: {}", e), color: 14 })
+            }
+            continue;
+        }
+        let mut n_moved = 0;
+        for x in &entry.undo {
+            match undo(factory, x).await {
+                Ok(n) => n_moved += n,
+                Err(e) => {
+                    // Stays pending and is retried next cycle, without holding up the other entries.
+                    alive!(factory, this);
+                    let text = local_fmt!("journal: undoing #{} of {} failed: {}", entry.id, entry.process, e);
+                    this.log(Log { text, color: 14 });
+                    continue 'entries;
+                }
+            }
+        }
+        alive!(factory, this);
+        let text = local_fmt!("journal: undid #{} of {}, {} items moved", entry.id, entry.process, n_moved);
+        this.log(Log { text, color: 6 });
+        let result = this.journal.as_ref().unwrap().borrow_mut().recovered(entry.id);
+        if let Err(e) = result {
+            this.log(Log { text: local_fmt!("journal: {}", e), color: 14 })
+        }
+    }
+    Ok(())
+}
+
 async fn update_storages(factory: &Weak<RefCell<Factory>>) -> Result<(), LocalStr> {
     let mut tasks = Vec::new();
     {
         alive!(factory, factory);
-        tasks.extend(factory.storages.iter().map(|storage| storage.borrow().update()));
+        tasks.extend(factory.storages.iter().map(|(storage, _)| storage.borrow().update()));
         tasks.extend(factory.fluid_storages.iter().map(|storage| storage.borrow().update()))
     };
     join_tasks(tasks).await?;
-    alive!(factory, this);
+    alive_mut!(factory, this);
     let mut n_total = 0;
     for (_, item) in &this.items {
         n_total += item.borrow().n_stored
     }
-    this.log(Log { text: local_fmt!("storage: {} items, {} types", n_total, this.items.len()), color: 13 });
+    let mut capacity = None;
+    for (storage, _) in &this.storages {
+        if let Some(x) = storage.borrow().capacity() {
+            *capacity.get_or_insert_with(Capacity::default) += x
+        }
+    }
+    let mut text = format!("storage: {} items, {} types", n_total, this.items.len());
+    if let Some(capacity) = &capacity {
+        text += &format!(", {}", capacity.describe())
+    }
+    if !this.bus_stuck.is_empty() {
+        text += &format!(", bus stuck on {} stacks", this.bus_stuck.len())
+    }
+    this.log(Log { text: text.into(), color: 13 });
+    if let Some(capacity) = capacity {
+        this.check_capacity(capacity)
+    }
     for (filter, n_backup) in &this.config.backups {
         if let Some((_, info)) = this.search_item(filter) {
             info.borrow_mut().n_backup += n_backup
@@ -614,17 +1162,20 @@ async fn bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, LocalStr>
     {
         alive_mut!(factory, this);
         let mut free_slots = Vec::new();
+        let mut stuck = Vec::new();
         for (slot, stack) in stacks.into_iter().enumerate() {
             if !this.bus_allocations.contains(&slot) {
                 if let Some(stack) = stack {
-                    this.deposit_item(slot, stack, &mut tasks);
+                    this.deposit_item(slot, stack, &mut tasks, &mut stuck);
                 } else {
                     free_slots.push(slot)
                 }
             }
         }
+        this.set_bus_stuck(stuck);
         while !free_slots.is_empty() && !this.bus_wait_queue.is_empty() {
             let slot = free_slots.pop().unwrap();
+            this.report(format_args!("bus slot {} allocated", slot));
             this.bus_allocations.insert(slot);
             this.bus_wait_queue.pop_front().unwrap().send(Ok(slot))
         }
@@ -691,6 +1242,7 @@ async fn fluid_bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, Loca
         }
         while !empty_buses.is_empty() && !this.fluid_bus_wait_queue.is_empty() {
             let bus = empty_buses.pop().unwrap();
+            this.report(format_args!("fluid bus {} allocated", bus));
             this.fluid_bus_allocations.insert(bus);
             this.fluid_bus_wait_queue.pop_front().unwrap().send(Ok(bus))
         }
@@ -699,6 +1251,7 @@ async fn fluid_bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, Loca
     alive_mut!(factory, this);
     while !empty_buses_after_deposit.is_empty() && !this.fluid_bus_wait_queue.is_empty() {
         let bus = empty_buses_after_deposit.pop().unwrap();
+        this.report(format_args!("fluid bus {} allocated", bus));
         this.fluid_bus_allocations.insert(bus);
         this.fluid_bus_wait_queue.pop_front().unwrap().send(Ok(bus))
     }
@@ -710,14 +1263,50 @@ async fn fluid_bus_update(factory: &Weak<RefCell<Factory>>) -> Result<bool, Loca
     Ok(ever_freed)
 }
 
+// Reports the peripherals that the layout uses on `client` but that are missing or of the wrong type there.
+async fn check_peripherals(factory: Weak<RefCell<Factory>>, client: LocalStr) {
+    let (tui, expected, action) = {
+        let Some(factory) = factory.upgrade() else { return };
+        let factory = factory.borrow();
+        let expected = factory.get_expected_peripherals(client);
+        if expected.peripherals.is_empty() {
+            return;
+        }
+        let action = ActionFuture::from(ListPeripherals);
+        factory.config.server.borrow().enqueue_request_group(&expected.client, vec![action.clone().into()]);
+        (factory.config.tui.clone(), expected, action)
+    };
+    let attached = match action.await {
+        Ok(x) => x,
+        Err(e) => return tui.log(format!("{}: peripheral check failed: {}", expected.client, e), 14),
+    };
+    // Plethora and older CC report only the block's own type.
+    let check_types = attached.values().flatten().any(|x| x == "inventory" || x == "fluid_storage");
+    let mut n_bad = 0;
+    for (addr, (kind, owner)) in &expected.peripherals {
+        let problem = match attached.get(addr) {
+            None => local_str!("not found"),
+            Some(types) => match kind.generic_type() {
+                Some(expected) if check_types && !types.iter().any(|x| x == expected) => {
+                    local_fmt!("is {}, not {}", types.join(", "), expected)
+                }
+                _ => continue,
+            },
+        };
+        tui.log(format!("{}: {} (used by {}) {}", expected.client, addr, owner, problem), 14);
+        n_bad += 1
+    }
+    let n_ok = expected.peripherals.len() - n_bad;
+    tui.log(format!("{}: {} of {} peripherals ok", expected.client, n_ok, expected.peripherals.len()), 0)
+}
+
 pub fn read_tanks<'a, T: GetClient + 'a>(
     server: &Server,
     accesses: impl IntoIterator<Item = &'a T>,
     tank_addr: impl Fn(&'a T) -> LocalStr,
 ) -> impl Future<Output = Result<BTreeMap<usize, (LocalStr, i64)>, LocalStr>> + 'static {
-    let access = server.load_balance(accesses);
-    let action = ActionFuture::from(Call { addr: tank_addr(access), args: vec!["tanks".into()] });
-    server.enqueue_request_group(access.get_client(), vec![action.clone().into()]);
+    let action =
+        server.call_with_failover(accesses, |access| Call { addr: tank_addr(access), args: vec!["tanks".into()] });
     async move {
         let mut result = BTreeMap::new();
         for (k, v) in call_result::<Table>(action.await?)? {
@@ -0,0 +1,247 @@
+use crate::item::Item;
+use crate::Tui;
+use flexstr::{local_fmt, LocalStr};
+use fnv::FnvHashMap;
+use hex::ToHex;
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    fs::{read_to_string, rename, write, OpenOptions},
+    io::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub struct HistoryConfig {
+    pub file_name: LocalStr,
+    // Minimum time between two samples.
+    pub interval: Duration,
+    // How far back samples are kept for rate queries, in memory and in the file.
+    pub retention: Duration,
+}
+
+// 'i' for items, 'f' for fluids.
+type Key = (char, LocalStr);
+
+// `n_in` and `n_out` are the amounts deposited and reserved since the previous sample.
+#[derive(Clone, Copy)]
+struct Sample {
+    time: u64,
+    n_stored: i64,
+    n_in: i64,
+    n_out: i64,
+}
+
+struct Series {
+    label: LocalStr,
+    samples: VecDeque<Sample>,
+}
+
+#[derive(Default)]
+struct Flow {
+    n_in: i64,
+    n_out: i64,
+}
+
+pub struct Rate {
+    pub label: LocalStr,
+    pub n_in: f64,
+    pub n_out: f64,
+    pub net: f64,
+}
+
+// TSV of `time, kind, key, label, n_stored, n_in, n_out`, appended to and rewritten with only the retained samples
+// once it holds twice as many rows. A row is only written when the series changed since its previous row.
+pub struct History {
+    file_name: LocalStr,
+    interval: Duration,
+    retention: Duration,
+    series: FnvHashMap<Key, Series>,
+    flows: FnvHashMap<Key, Flow>,
+    first_time: Option<u64>,
+    last_time: Option<u64>,
+    n_file_rows: usize,
+}
+
+// Below this, the file isn't compacted however few samples are retained.
+const MIN_ROWS_TO_COMPACT: usize = 4096;
+
+fn now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs()) }
+
+pub fn item_key(item: &Item) -> LocalStr {
+    let mut result = item.name.to_std_string();
+    if let Some(nbt_hash) = item.nbt_hash {
+        result += "#";
+        result += &nbt_hash.encode_hex::<String>()
+    }
+    if let Some(damage) = item.damage {
+        result += &format!(":{}", damage)
+    }
+    result.into()
+}
+
+fn format_row((kind, key): &Key, label: &str, x: &Sample) -> String {
+    format!("{}\t{kind}\t{key}\t{label}\t{}\t{}\t{}\n", x.time, x.n_stored, x.n_in, x.n_out)
+}
+
+fn parse_row(line: &str) -> Option<(Key, LocalStr, Sample)> {
+    let mut fields = line.split('\t');
+    let time = fields.next()?.parse().ok()?;
+    let kind = fields.next()?.chars().next()?;
+    let key = fields.next()?.into();
+    let label = fields.next()?.into();
+    let n_stored = fields.next()?.parse().ok()?;
+    let n_in = fields.next()?.parse().ok()?;
+    let n_out = fields.next()?.parse().ok()?;
+    Some(((kind, key), label, Sample { time, n_stored, n_in, n_out }))
+}
+
+impl History {
+    pub fn new(tui: &Tui, config: &HistoryConfig) -> Self {
+        let mut result = Self {
+            file_name: config.file_name.clone(),
+            interval: config.interval,
+            retention: config.retention,
+            series: FnvHashMap::default(),
+            flows: FnvHashMap::default(),
+            first_time: None,
+            last_time: None,
+            n_file_rows: 0,
+        };
+        match read_to_string(&*result.file_name) {
+            Ok(data) => {
+                let mut n_rows = 0;
+                for (key, label, sample) in data.lines().filter_map(parse_row) {
+                    result.first_time.get_or_insert(sample.time);
+                    result.push(key, label, sample);
+                    n_rows += 1
+                }
+                result.n_file_rows = n_rows;
+                result.trim(now());
+                tui.log(format!("history loaded with {} rows", n_rows), 0)
+            }
+            Err(e) => tui.log(format!("history not loaded: {}", e), 0),
+        }
+        result
+    }
+
+    fn push(&mut self, key: Key, label: LocalStr, sample: Sample) {
+        let series =
+            self.series.entry(key).or_insert_with(|| Series { label: label.clone(), samples: VecDeque::new() });
+        series.label = label;
+        series.samples.push_back(sample)
+    }
+
+    // Keeps the last sample before the retention window so that the stored amount at its start is known.
+    fn trim(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.retention.as_secs());
+        self.series.retain(|_, series| {
+            while series.samples.len() > 1 && series.samples[1].time <= cutoff {
+                series.samples.pop_front();
+            }
+            series.samples.back().is_some_and(|x| x.n_stored != 0 || x.time > cutoff)
+        })
+    }
+
+    pub fn record_in(&mut self, kind: char, key: LocalStr, n: i64) {
+        self.flows.entry((kind, key)).or_default().n_in += n
+    }
+    pub fn record_out(&mut self, kind: char, key: LocalStr, n: i64) {
+        self.flows.entry((kind, key)).or_default().n_out += n
+    }
+
+    pub fn is_due(&self) -> bool { self.last_time.is_none_or(|x| now() >= x + self.interval.as_secs()) }
+
+    // `stored` has every item and fluid currently in storage, with their labels.
+    pub fn sample(&mut self, stored: Vec<(Key, LocalStr, i64)>) -> Result<(), LocalStr> {
+        let time = now();
+        let mut current = FnvHashMap::<Key, (LocalStr, i64)>::default();
+        for (key, label, n_stored) in stored {
+            current.insert(key, (label, n_stored));
+        }
+        // Series that disappeared from storage drop to zero.
+        for (key, series) in &self.series {
+            if !current.contains_key(key) {
+                current.insert(key.clone(), (series.label.clone(), 0));
+            }
+        }
+        let mut rows = String::new();
+        for (key, (label, n_stored)) in current {
+            let Flow { n_in, n_out } = self.flows.remove(&key).unwrap_or_default();
+            let last = self.series.get(&key).and_then(|x| x.samples.back());
+            if n_in == 0 && n_out == 0 && last.map_or(0, |x| x.n_stored) == n_stored {
+                continue;
+            }
+            let label = label.replace('\t', " ");
+            let sample = Sample { time, n_stored, n_in, n_out };
+            rows += &format_row(&key, &label, &sample);
+            self.n_file_rows += 1;
+            self.push(key, label.into(), sample)
+        }
+        self.flows.clear();
+        self.first_time.get_or_insert(time);
+        self.last_time = Some(time);
+        self.trim(time);
+        if self.n_file_rows > (2 * self.n_rows()).max(MIN_ROWS_TO_COMPACT) {
+            return self.compact();
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&*self.file_name)
+            .map_err(|e| local_fmt!("{}: {}", self.file_name, e))?;
+        file.write_all(rows.as_bytes()).map_err(|e| local_fmt!("{}: {}", self.file_name, e))
+    }
+
+    fn n_rows(&self) -> usize { self.series.values().map(|x| x.samples.len()).sum() }
+
+    // Replaces the file atomically with the retained samples.
+    fn compact(&mut self) -> Result<(), LocalStr> {
+        let mut rows = Vec::new();
+        for (key, series) in &self.series {
+            rows.extend(series.samples.iter().map(|x| (x.time, format_row(key, &series.label, x))))
+        }
+        rows.sort_by_key(|x| x.0);
+        self.n_file_rows = rows.len();
+        let tmp = format!("{}.tmp", self.file_name);
+        write(&tmp, String::from_iter(rows.into_iter().map(|x| x.1))).map_err(|e| local_fmt!("{}: {}", tmp, e))?;
+        rename(&tmp, &*self.file_name).map_err(|e| local_fmt!("{}: {}", self.file_name, e))
+    }
+
+    // Per-hour rates over the last `window` of series matching `regex` (on the label, or on the key if `by_name`).
+    pub fn get_rates(&self, window: Duration, regex: &Regex, by_name: bool) -> Vec<Rate> {
+        let now = now();
+        let start = now.saturating_sub(window.as_secs());
+        let covered = now - start.max(self.first_time.unwrap_or(now));
+        if covered == 0 {
+            return Vec::new();
+        }
+        let scale = 3600. / covered as f64;
+        let mut result = Vec::new();
+        for ((_, key), series) in &self.series {
+            if !regex.is_match(if by_name { key } else { &series.label }) {
+                continue;
+            }
+            let (mut n_start, mut n_in, mut n_out) = (0, 0, 0);
+            for sample in &series.samples {
+                if sample.time <= start {
+                    n_start = sample.n_stored
+                } else {
+                    n_in += sample.n_in;
+                    n_out += sample.n_out
+                }
+            }
+            let n_now = series.samples.back().map_or(0, |x| x.n_stored);
+            if n_in == 0 && n_out == 0 && n_now == n_start {
+                continue;
+            }
+            result.push(Rate {
+                label: series.label.clone(),
+                n_in: n_in as f64 * scale,
+                n_out: n_out as f64 * scale,
+                net: (n_now - n_start) as f64 * scale,
+            })
+        }
+        result.sort_by(|x, y| x.net.partial_cmp(&y.net).unwrap());
+        result
+    }
+}
@@ -0,0 +1,320 @@
+use crate::action::Log;
+use crate::factory::Factory;
+use crate::item::{parse_query, Api};
+use crate::recipe::PlanRecipe;
+use crate::util::{alive, spawn};
+use crate::{server::create_listener, Tui};
+use abort_on_drop::ChildTask;
+use flexstr::{local_fmt, local_str, LocalStr};
+use hex::ToHex;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    net::SocketAddr,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+const MAX_HEAD_SIZE: usize = 8192;
+const MAX_BODY_SIZE: usize = 65536;
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    // Names are lowercase.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(x, _)| x == name).map(|(_, value)| value.as_str())
+    }
+
+    fn get_token(&self) -> Option<&str> { self.get_header("authorization")?.strip_prefix("Bearer ") }
+}
+
+type Response = Result<Value, (&'static str, LocalStr)>;
+
+fn bad_request(e: impl Into<LocalStr>) -> (&'static str, LocalStr) { ("400 Bad Request", e.into()) }
+
+// Decodes a `application/x-www-form-urlencoded` component.
+fn decode_component(x: &str) -> Option<String> {
+    let mut result = Vec::new();
+    let mut bytes = x.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => result.push(b' '),
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                result.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?)
+            }
+            _ => result.push(byte),
+        }
+    }
+    String::from_utf8(result).ok()
+}
+
+fn get_param(query: &str, name: &str) -> Result<Option<String>, (&'static str, LocalStr)> {
+    for pair in query.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if key == name {
+            return decode_component(value).map(Some).ok_or_else(|| bad_request(local_fmt!("invalid {}", name)));
+        }
+    }
+    Ok(None)
+}
+
+fn get_items(factory: &Factory, filter: Option<String>) -> Response {
+    let filter = parse_query(&filter.unwrap_or_default()).map_err(bad_request)?;
+    let mut result = Vec::new();
+    for x in &factory.snapshot.items {
+        if filter.apply(&x.item, &x.detail) {
+            result.push(json!({
+                "name": &*x.item.name,
+                "nbt": x.item.nbt_hash.map(|x| x.encode_hex::<String>()),
+                "label": &*x.detail.label,
+                "count": x.size,
+                "max_size": x.detail.max_size,
+            }))
+        }
+    }
+    Ok(result.into())
+}
+
+fn get_fluids(factory: &Factory) -> Response {
+    let fluids = factory.snapshot.fluids.iter();
+    Ok(Vec::from_iter(fluids.map(|(fluid, qty)| json!({ "fluid": &**fluid, "amount": qty }))).into())
+}
+
+fn recipe_to_json(recipe: &PlanRecipe) -> Value {
+    let products = recipe.products.iter().map(|x| json!({ "item": &*x.item.describe(), "n": x.n_per_set }));
+    let inputs = recipe.inputs.iter().map(|x| json!({ "item": &*x.item.describe(), "n": x.size }));
+    json!({ "products": Vec::from_iter(products), "inputs": Vec::from_iter(inputs) })
+}
+
+// Only processes with recipes are listed, grouped by name in configuration order.
+fn get_processes(factory: &Factory) -> Response {
+    let mut processes: Vec<(LocalStr, Vec<Value>)> = Vec::new();
+    for recipe in factory.get_recipes() {
+        match processes.last_mut() {
+            Some((name, recipes)) if *name == recipe.process => recipes.push(recipe_to_json(&recipe)),
+            _ => processes.push((recipe.process.clone(), vec![recipe_to_json(&recipe)])),
+        }
+    }
+    Ok(Vec::from_iter(processes.into_iter().map(|(name, recipes)| json!({ "name": &*name, "recipes": recipes })))
+        .into())
+}
+
+fn get_clients(factory: &Factory) -> Response {
+    let mut result = Vec::new();
+    let server = factory.config.server.borrow();
+    for x in server.get_clients() {
+        result.push(json!({
+            "addr": x.addr.to_string(),
+            "login": x.login.as_deref(),
+            "load": x.load,
+            "queued": x.n_queued,
+            "in_flight": x.n_in_flight,
+            "latency_ms": x.latency.map(|x| x.as_secs_f64() * 1000.),
+            "protocol": x.protocol,
+            "api": x.login.as_deref().map(|x| server.get_api(x).name()),
+            "capabilities": x.capabilities.map(|x| json!({
+                "api": x.api.map(Api::name),
+                "turtle": x.turtle,
+                "batch": x.batch,
+                "computer_id": x.computer_id,
+                "peripherals": BTreeMap::from_iter(x.peripherals.iter().map(|(name, kind)| (&**name, &**kind))),
+            })),
+        }))
+    }
+    Ok(result.into())
+}
+
+fn get_orders(factory: &Factory) -> Response {
+    let mut result = Vec::new();
+    for output in &factory.order_outputs {
+        let output = output.borrow();
+        for x in &output.orders {
+            result.push(json!({
+                "id": x.id,
+                "output": &*output.config.name,
+                "item": &*x.item.describe(),
+                "n_wanted": x.n_wanted,
+                "n_delivered": x.n_delivered,
+                "n_in_flight": x.n_in_flight,
+            }))
+        }
+    }
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrderBody {
+    output: String,
+    // Same syntax as `/items?filter=`.
+    item: String,
+    n: i32,
+}
+
+fn token_mac(token: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"cc-remote order token").unwrap();
+    mac.update(token.as_bytes());
+    mac
+}
+
+// Compares digests in constant time (`verify_slice`) and checks every token, so timing doesn't reveal a match.
+fn check_token(token: Option<&str>, tokens: &[LocalStr]) -> Result<(), (&'static str, LocalStr)> {
+    let Some(token) = token else { return Err(("401 Unauthorized", local_str!("missing bearer token"))) };
+    let digest = token_mac(token).finalize().into_bytes();
+    if tokens.iter().fold(false, |found, x| token_mac(x).verify_slice(&digest).is_ok() | found) {
+        Ok(())
+    } else {
+        Err(("403 Forbidden", local_str!("token not allowed for this output")))
+    }
+}
+
+fn post_order(factory: &mut Factory, request: &Request) -> Response {
+    let body: OrderBody =
+        serde_json::from_slice(&request.body).map_err(|e| bad_request(local_fmt!("invalid body: {}", e)))?;
+    if body.n <= 0 {
+        return Err(bad_request("n must be positive"));
+    }
+    let item = parse_query(&body.item).map_err(bad_request)?;
+    let Some(output) = factory.get_order_output(&body.output).cloned() else {
+        return Err(("404 Not Found", local_fmt!("unknown output: {}", body.output)));
+    };
+    check_token(request.get_token(), &output.borrow().config.tokens)?;
+    Ok(json!({ "id": factory.add_order(&output, item, body.n) }))
+}
+
+fn cancel_order(factory: &Factory, request: &Request) -> Response {
+    let id = get_param(&request.query, "id")?.and_then(|x| x.parse::<usize>().ok());
+    let id = id.ok_or_else(|| bad_request("missing or invalid id"))?;
+    for output in &factory.order_outputs {
+        let mut output = output.borrow_mut();
+        if let Some(pos) = output.orders.iter().position(|x| x.id == id) {
+            check_token(request.get_token(), &output.config.tokens)?;
+            let order = output.orders.remove(pos);
+            let text = local_fmt!("order #{} cancelled after {}/{}", id, order.n_delivered, order.n_wanted);
+            factory.log(Log { text, color: 6 });
+            return Ok(json!({ "id": id }));
+        }
+    }
+    Err(("404 Not Found", local_fmt!("unknown order: {}", id)))
+}
+
+fn route(factory: &Weak<RefCell<Factory>>, request: &Request) -> Response {
+    let factory = alive(factory).map_err(|e| ("503 Service Unavailable", e))?;
+    let mut factory = factory.borrow_mut();
+    match (&*request.method, &*request.path) {
+        ("GET", "/items") => get_items(&factory, get_param(&request.query, "filter")?),
+        ("GET", "/fluids") => get_fluids(&factory),
+        ("GET", "/processes") => get_processes(&factory),
+        ("GET", "/clients") => get_clients(&factory),
+        ("GET", "/orders") => get_orders(&factory),
+        ("POST", "/orders") => post_order(&mut factory, request),
+        ("DELETE", "/orders") => cancel_order(&factory, request),
+        (_, "/items" | "/fluids" | "/processes" | "/clients" | "/orders") => {
+            Err(("405 Method Not Allowed", local_fmt!("unsupported method: {}", request.method)))
+        }
+        _ => Err(("404 Not Found", local_fmt!("unknown path: {}", request.path))),
+    }
+}
+
+async fn read_more(stream: &mut TcpStream, data: &mut Vec<u8>) -> Result<(), LocalStr> {
+    let mut buffer = [0; 1024];
+    let n = stream.read(&mut buffer).await.map_err(|e| local_fmt!("error reading: {}", e))?;
+    if n == 0 {
+        return Err(local_str!("disconnected before end of request"));
+    }
+    data.extend_from_slice(&buffer[..n]);
+    Ok(())
+}
+
+fn parse_head(head: &str) -> Option<Request> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_owned();
+    let target = request_line.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut headers = Vec::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+    }
+    Some(Request { method, path: path.to_owned(), query: query.to_owned(), headers, body: Vec::new() })
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request, LocalStr> {
+    let mut data = Vec::new();
+    let head_size = loop {
+        if let Some(pos) = data.windows(4).position(|x| x == b"\r\n\r\n") {
+            break pos;
+        } else if data.len() > MAX_HEAD_SIZE {
+            return Err(local_str!("request too large"));
+        }
+        read_more(stream, &mut data).await?
+    };
+    let head = std::str::from_utf8(&data[..head_size]).map_err(|_| local_str!("non-UTF-8 request"))?;
+    let mut request = parse_head(head).ok_or_else(|| local_str!("malformed request"))?;
+    let body_size = match request.get_header("content-length") {
+        Some(x) => x.parse().map_err(|_| local_str!("invalid content-length"))?,
+        None => 0,
+    };
+    if body_size > MAX_BODY_SIZE {
+        return Err(local_str!("request too large"));
+    }
+    let body_start = head_size + 4;
+    while data.len() < body_start + body_size {
+        read_more(stream, &mut data).await?
+    }
+    request.body = data.split_off(body_start);
+    request.body.truncate(body_size);
+    Ok(request)
+}
+
+async fn handle_connection(factory: &Weak<RefCell<Factory>>, mut stream: TcpStream) -> Result<(), LocalStr> {
+    let request = read_request(&mut stream).await?;
+    let (status, body) = match route(factory, &request) {
+        Ok(body) => (if request.method == "POST" { "202 Accepted" } else { "200 OK" }, body),
+        Err((status, e)) => (status, json!({ "error": &*e })),
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await.map_err(|e| local_fmt!("error writing: {}", e))
+}
+
+async fn connection_main(tui: Rc<Tui>, factory: Weak<RefCell<Factory>>, stream: TcpStream, addr: SocketAddr) {
+    let result = timeout(Duration::from_secs(30), handle_connection(&factory, stream)).await;
+    if let Err(e) = result.unwrap_or_else(|_| Err(local_str!("request timeout"))) {
+        tui.log(format!("http {}: {}", addr, e), 0)
+    }
+}
+
+// JSON endpoints: read-only `/items[?filter=]`, `/fluids`, `/processes` and `/clients`,
+// and `/orders`, where POST and DELETE require a bearer token of the order output.
+pub async fn http_main(tui: Rc<Tui>, factory: Weak<RefCell<Factory>>, port: u16) {
+    let listener = create_listener(port);
+    let mut connections: Vec<ChildTask<()>> = Vec::new();
+    loop {
+        let (stream, addr) = listener.accept().await.unwrap();
+        connections.retain(|x| !x.is_finished());
+        connections.push(spawn(connection_main(tui.clone(), factory.clone(), stream, addr)))
+    }
+}
@@ -1,5 +1,5 @@
 use super::access::{GetAddr, GetClient};
-use super::action::{ActionFuture, Call};
+use super::action::{Call, CallMany};
 use super::detail_cache::{DetailCache, DetailResult};
 use super::item::{DetailStack, ItemStack};
 use super::lua_value::{call_result, table_to_vec, Value};
@@ -38,23 +38,47 @@ macro_rules! impl_inventory {
 
 fn fetch_detail<T: Inventory>(this: &T, slot: usize) -> impl Future<Output = Result<DetailStack, LocalStr>> {
     let server = this.get_server().borrow();
-    let access = server.load_balance(this.get_accesses());
-    let action = ActionFuture::from(Call {
+    let action = server.call_with_failover_from(this.get_accesses(), |access| Call {
         addr: access.get_addr().clone(),
-        args: vec![if cfg!(feature = "plethora") { "getItemMeta" } else { "getItemDetail" }.into(), (slot + 1).into()],
+        args: vec![server.get_api(access.get_client()).detail_method().into(), (slot + 1).into()],
     });
-    server.enqueue_request_group(access.get_client(), vec![action.clone().into()]);
-    async move { DetailStack::parse(call_result(action.await?)?) }
+    let server = this.get_server().clone();
+    async move {
+        let (client, result) = action.await?;
+        let api = server.borrow().get_api(&client);
+        DetailStack::parse(call_result(result)?, api)
+    }
+}
+
+// All slots in one request, for when every access's client supports `CallMany`.
+fn fetch_details<T: Inventory>(
+    this: &T,
+    slots: Vec<usize>,
+) -> impl Future<Output = Result<Vec<Result<DetailStack, LocalStr>>, LocalStr>> {
+    let server = this.get_server().borrow();
+    let action = server.call_with_failover_from(this.get_accesses(), |access| CallMany {
+        addr: access.get_addr().clone(),
+        method: server.get_api(access.get_client()).detail_method().into(),
+        args: Vec::from_iter(slots.iter().map(|slot| vec![(slot + 1).into()])),
+    });
+    let server = this.get_server().clone();
+    async move {
+        let (client, results) = action.await?;
+        let api = server.borrow().get_api(&client);
+        Ok(Vec::from_iter(results.into_iter().map(|x| DetailStack::parse(call_result(x?)?, api))))
+    }
 }
 
 fn fetch_detail_list<T: Inventory>(this: &T) -> impl Future<Output = Result<Vec<Option<DetailStack>>, LocalStr>> {
     let server = this.get_server().borrow();
-    let access = server.load_balance(this.get_accesses());
-    let action = ActionFuture::from(Call { addr: access.get_addr().clone(), args: vec!["list".into()] });
-    server.enqueue_request_group(access.get_client(), vec![action.clone().into()]);
+    let action = server.call_with_failover_from(this.get_accesses(), |access| Call {
+        addr: access.get_addr().clone(),
+        args: vec!["list".into()],
+    });
     let weak = this.get_weak().clone();
     async move {
-        let stacks = table_to_vec(call_result(action.await?)?)?;
+        let (client, stacks) = action.await?;
+        let stacks = table_to_vec(call_result(stacks)?)?;
         let mut details = Vec::new();
         details.resize_with(stacks.len(), || None);
         let details = Rc::new(RefCell::new(details));
@@ -63,11 +87,16 @@ fn fetch_detail_list<T: Inventory>(this: &T) -> impl Future<Output = Result<Vec<
             alive!(weak, this);
             let mut details_ref = details.borrow_mut();
             let mut detail_cache = this.get_detail_cache().borrow_mut();
+            let server = this.get_server().borrow();
+            let api = server.get_api(&client);
+            let batch =
+                this.get_accesses().iter().all(|x| server.get_capabilities(x.get_client()).is_some_and(|x| x.batch));
+            let mut misses = Vec::new();
             for (slot, stack) in stacks.into_iter().enumerate() {
                 if let Value::N = stack {
                     continue;
                 }
-                let stack = ItemStack::parse(stack)?;
+                let stack = ItemStack::parse(stack, api)?;
                 match detail_cache.query(&stack.item) {
                     DetailResult::Resolved(x) => details_ref[slot] = Some(stack.with_detail(x.clone())),
                     DetailResult::Resolving { sender, receiver } => {
@@ -77,15 +106,34 @@ fn fetch_detail_list<T: Inventory>(this: &T) -> impl Future<Output = Result<Vec<
                             Ok(())
                         }));
                         if let Some(sender) = sender {
-                            let detail = fetch_detail(this, slot);
-                            tasks.push(spawn(async move {
-                                sender.send(detail.await.map(|stack| (stack.item, stack.detail)));
-                                Ok(())
-                            }))
+                            if batch {
+                                misses.push((slot, sender))
+                            } else {
+                                let detail = fetch_detail(this, slot);
+                                tasks.push(spawn(async move {
+                                    sender.send(detail.await.map(|stack| (stack.item, stack.detail)));
+                                    Ok(())
+                                }))
+                            }
                         }
                     }
                 }
             }
+            if !misses.is_empty() {
+                let (slots, senders): (Vec<_>, Vec<_>) = misses.into_iter().unzip();
+                let details = fetch_details(this, slots);
+                tasks.push(spawn(async move {
+                    match details.await {
+                        Ok(details) => {
+                            for (sender, detail) in senders.into_iter().zip(details) {
+                                sender.send(detail.map(|stack| (stack.item, stack.detail)))
+                            }
+                        }
+                        Err(e) => senders.into_iter().for_each(|sender| sender.send(Err(e.clone()))),
+                    }
+                    Ok(())
+                }))
+            }
         }
         join_tasks(tasks).await?;
         Ok(Rc::into_inner(details).unwrap().into_inner())
@@ -94,9 +142,10 @@ fn fetch_detail_list<T: Inventory>(this: &T) -> impl Future<Output = Result<Vec<
 
 fn fetch_size<T: Inventory>(this: &T) -> impl Future<Output = Result<usize, LocalStr>> {
     let server = this.get_server().borrow();
-    let access = server.load_balance(this.get_accesses());
-    let action = ActionFuture::from(Call { addr: access.get_addr().clone(), args: vec!["size".into()] });
-    server.enqueue_request_group(access.get_client(), vec![action.clone().into()]);
+    let action = server.call_with_failover(this.get_accesses(), |access| Call {
+        addr: access.get_addr().clone(),
+        args: vec!["size".into()],
+    });
     let weak = this.get_weak().clone();
     async move {
         let size = call_result(action.await?)?;
@@ -1,21 +1,61 @@
 use super::lua_value::{table_remove, Table, Value};
 use flexstr::{local_fmt, LocalStr};
 use hex::{FromHex, ToHex};
+use regex::Regex;
 use std::{cmp::min, rc::Rc};
 
+// Peripheral flavour a client talks to, which decides the shape of items.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Api {
+    // CC:Tweaked's generic peripherals.
+    #[default]
+    Generic,
+    // Items have a damage value and details come from `getItemMeta`.
+    Plethora,
+}
+
+impl Api {
+    pub fn parse(name: &str) -> Result<Self, LocalStr> {
+        match name {
+            "generic" => Ok(Api::Generic),
+            "plethora" => Ok(Api::Plethora),
+            _ => Err(local_fmt!("unknown peripheral api: {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Api::Generic => "generic",
+            Api::Plethora => "plethora",
+        }
+    }
+
+    pub fn detail_method(self) -> &'static str {
+        match self {
+            Api::Generic => "getItemDetail",
+            Api::Plethora => "getItemMeta",
+        }
+    }
+
+    fn nbt_hash_key(self) -> &'static str {
+        match self {
+            Api::Generic => "nbt",
+            Api::Plethora => "nbtHash",
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash)]
 pub struct Item {
     pub name: LocalStr,
     pub nbt_hash: Option<[u8; 16]>,
-    #[cfg(feature = "plethora")]
-    pub damage: i16,
+    // Only with Plethora.
+    pub damage: Option<i16>,
 }
 
-const NBT_HASH_KEY: &'static str = if cfg!(feature = "plethora") { "nbtHash" } else { "nbt" };
-
-fn remove_nbt_hash(table: &mut Table) -> Result<Option<[u8; 16]>, LocalStr> {
+fn remove_nbt_hash(table: &mut Table, key: &'static str) -> Result<Option<[u8; 16]>, LocalStr> {
     table
-        .remove(&NBT_HASH_KEY.into())
+        .remove(&key.into())
         .map(LocalStr::try_from)
         .transpose()?
         .map(|x| <_>::from_hex(&*x))
@@ -24,22 +64,35 @@ fn remove_nbt_hash(table: &mut Table) -> Result<Option<[u8; 16]>, LocalStr> {
 }
 
 impl Item {
-    pub fn parse_part(table: &mut Table) -> Result<Rc<Self>, LocalStr> {
+    pub fn parse_part(table: &mut Table, api: Api) -> Result<Rc<Self>, LocalStr> {
         Ok(Rc::new(Self {
             name: table_remove(table, "name")?,
-            nbt_hash: remove_nbt_hash(table)?,
-            #[cfg(feature = "plethora")]
-            damage: table_remove(table, "damage")?,
+            nbt_hash: remove_nbt_hash(table, api.nbt_hash_key())?,
+            damage: if api == Api::Plethora { Some(table_remove(table, "damage")?) } else { None },
         }))
     }
 
+    // Inverse of `encode`. Entries saved before the flavour was recorded are generic unless they have an `nbtHash`
+    // or a `damage`, which only Plethora reports.
+    pub fn decode(table: &mut Table) -> Result<Rc<Self>, LocalStr> {
+        let api = match table.remove(&"api".into()) {
+            Some(api) => Api::parse(&LocalStr::try_from(api)?)?,
+            None if table.contains_key(&"nbtHash".into()) || table.contains_key(&"damage".into()) => Api::Plethora,
+            None => Api::Generic,
+        };
+        Self::parse_part(table, api)
+    }
+
     pub fn encode(&self, table: &mut Table) {
+        let api = if self.damage.is_some() { Api::Plethora } else { Api::Generic };
         table.insert("name".into(), self.name.clone().into());
         if let Some(nbt_hash) = self.nbt_hash {
-            table.insert(NBT_HASH_KEY.into(), nbt_hash.encode_hex::<LocalStr>().into());
+            table.insert(api.nbt_hash_key().into(), nbt_hash.encode_hex::<LocalStr>().into());
+        }
+        if let Some(damage) = self.damage {
+            table.insert("api".into(), api.name().into());
+            table.insert("damage".into(), damage.into());
         }
-        #[cfg(feature = "plethora")]
-        table.insert("damage".into(), self.damage.into());
     }
 }
 
@@ -49,13 +102,13 @@ pub struct ItemStack {
 }
 
 impl ItemStack {
-    fn parse_part(table: &mut Table) -> Result<Self, LocalStr> {
-        Ok(Self { item: Item::parse_part(table)?, size: table_remove(table, "count")? })
+    fn parse_part(table: &mut Table, api: Api) -> Result<Self, LocalStr> {
+        Ok(Self { item: Item::parse_part(table, api)?, size: table_remove(table, "count")? })
     }
 
-    pub fn parse(value: Value) -> Result<Self, LocalStr> {
+    pub fn parse(value: Value, api: Api) -> Result<Self, LocalStr> {
         let mut table = Table::try_from(value)?;
-        let result = Self::parse_part(&mut table)?;
+        let result = Self::parse_part(&mut table, api)?;
         if table.is_empty() {
             Ok(result)
         } else {
@@ -97,8 +150,8 @@ pub struct DetailStack {
 }
 
 impl DetailStack {
-    pub fn parse(mut table: Table) -> Result<Self, LocalStr> {
-        let stack = ItemStack::parse_part(&mut table)?;
+    pub fn parse(mut table: Table, api: Api) -> Result<Self, LocalStr> {
+        let stack = ItemStack::parse_part(&mut table, api)?;
         Ok(stack.with_detail(Detail::parse(table)?))
     }
 }
@@ -120,12 +173,42 @@ impl Filter {
             Filter::Custom { func, .. } => func(item, detail),
         }
     }
+
+    pub fn describe(&self) -> LocalStr {
+        match self {
+            Filter::Label(x) => x.clone(),
+            Filter::Name(x) => local_fmt!("<{}>", x),
+            Filter::Both { label, name } => local_fmt!("{} <{}>", label, name),
+            Filter::Custom { desc, .. } => local_fmt!("<{}>", desc),
+        }
+    }
+}
+
+// Same syntax as the manual UI: a regex on the label, or on the name if prefixed with '='.
+pub fn parse_query(query: &str) -> Result<Filter, LocalStr> {
+    let (needle, by_name) = query.strip_prefix('=').map_or((query, false), |x| (x, true));
+    let regex = Regex::new(needle).map_err(|e| local_fmt!("invalid regex: {}", e))?;
+    let func = move |item: &Item, detail: &Detail| regex.is_match(if by_name { &item.name } else { &detail.label });
+    Ok(Filter::Custom { desc: query.into(), func: Rc::new(func) })
+}
+
+// Custom filters are compared by their description.
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Filter::Label(x), Filter::Label(y)) => x == y,
+            (Filter::Name(x), Filter::Name(y)) => x == y,
+            (Filter::Both { label: lx, name: nx }, Filter::Both { label: ly, name: ny }) => lx == ly && nx == ny,
+            (Filter::Custom { desc: x, .. }, Filter::Custom { desc: y, .. }) => x == y,
+            _ => false,
+        }
+    }
 }
 
 pub fn jammer() -> DetailStack {
     thread_local!(static STACK: DetailStack = DetailStack {
         size: 1,
-        item: Rc::new(Item { name: <_>::default(), nbt_hash: None, #[cfg(feature = "plethora")] damage: 0 }),
+        item: Rc::new(Item { name: <_>::default(), nbt_hash: None, damage: None }),
         detail: Rc::new(Detail { label: <_>::default(), max_size: 1, others: Table::new() })
     });
     STACK.with(|stack| stack.clone())
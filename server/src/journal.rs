@@ -0,0 +1,89 @@
+use crate::Tui;
+use flexstr::{local_fmt, LocalStr};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, rename, write},
+};
+
+// A step that puts items touched by an interrupted job back where they belong.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Undo {
+    // Sends whatever is left in `slot` of `inv_addr` back to storage through a bus slot.
+    Return { client: String, bus_addr: String, inv_addr: String, slot: usize },
+    // `addr.method(other, from_slot, 64, to_slot)`, e.g. to put a non-consumable back into its storage slot.
+    Move { client: String, addr: String, method: String, other: String, from_slot: usize, to_slot: usize },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub id: usize,
+    pub process: String,
+    // Run in order.
+    pub undo: Vec<Undo>,
+}
+
+// Jobs that leave items outside storage and the bus while running (e.g. on a crafting grid),
+// persisted as a JSON array of entries that is replaced atomically on every change.
+pub struct Journal {
+    file_name: LocalStr,
+    next_id: usize,
+    entries: BTreeMap<usize, Entry>,
+    // Entries left open by a previous run or by a failed job, to be undone.
+    pending: Vec<usize>,
+}
+
+impl Journal {
+    pub fn new(tui: &Tui, file_name: LocalStr) -> Self {
+        let mut result = Self { file_name, next_id: 0, entries: BTreeMap::new(), pending: Vec::new() };
+        let entries = read_to_string(&*result.file_name)
+            .map_err(|e| local_fmt!("{}", e))
+            .and_then(|x| serde_json::from_str::<Vec<Entry>>(&x).map_err(|e| local_fmt!("{}", e)));
+        match entries {
+            Ok(entries) => {
+                for entry in entries {
+                    result.next_id = result.next_id.max(entry.id + 1);
+                    result.pending.push(entry.id);
+                    result.entries.insert(entry.id, entry);
+                }
+                tui.log(format!("journal loaded with {} open entries", result.pending.len()), 0)
+            }
+            Err(e) => tui.log(format!("journal not loaded: {}", e), 0),
+        }
+        result
+    }
+
+    fn save(&self) -> Result<(), LocalStr> {
+        let data = serde_json::to_string(&Vec::from_iter(self.entries.values())).unwrap();
+        let tmp = format!("{}.tmp", self.file_name);
+        write(&tmp, data).map_err(|e| local_fmt!("{}: {}", tmp, e))?;
+        rename(&tmp, &*self.file_name).map_err(|e| local_fmt!("{}: {}", self.file_name, e))
+    }
+
+    // Bus slots aren't recorded, since anything stranded there is deposited by the first bus update.
+    pub fn begin(&mut self, process: &str, undo: Vec<Undo>) -> Result<usize, LocalStr> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(id, Entry { id, process: process.to_owned(), undo });
+        self.save().map(|_| id)
+    }
+
+    // A failed job stays open and is undone before the next cycle's processes run.
+    pub fn end(&mut self, id: usize, succeeded: bool) -> Result<(), LocalStr> {
+        if !succeeded {
+            self.pending.push(id);
+            return Ok(());
+        }
+        self.entries.remove(&id);
+        self.save()
+    }
+
+    pub fn get_pending(&self) -> Vec<Entry> { Vec::from_iter(self.pending.iter().map(|id| self.entries[id].clone())) }
+
+    pub fn recovered(&mut self, id: usize) -> Result<(), LocalStr> {
+        self.pending.retain(|x| *x != id);
+        self.end(id, true)
+    }
+}
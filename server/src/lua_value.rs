@@ -1,4 +1,5 @@
 use flexstr::{local_fmt, LocalStr};
+use fnv::FnvHashMap;
 use num_traits::cast::{AsPrimitive, FromPrimitive};
 use ordered_float::NotNan;
 use std::{collections::BTreeMap, io::Write};
@@ -229,19 +230,144 @@ pub fn serialize(x: &Value, out: &mut Vec<u8>) {
     }
 }
 
+// Protocol versions, negotiated at login: the text encoding above, and the compact one below.
+pub const PROTOCOL_TEXT: u8 = 1;
+pub const PROTOCOL_COMPACT: u8 = 2;
+
+// Tags of the compact encoding, where `0` also ends a table. Lengths and non-negative integers are LEB128 varints.
+// Each top-level value numbers its strings in order of appearance, so that repeated ones (e.g. item names in `list`)
+// are sent once and then referred to by index.
+const C_NIL: u8 = 0;
+const C_FALSE: u8 = 1;
+const C_TRUE: u8 = 2;
+const C_NUM: u8 = 3; // length-prefixed text, for numbers that aren't non-negative integers
+const C_UINT: u8 = 4;
+const C_STR: u8 = 5;
+const C_REF: u8 = 6;
+const C_TABLE: u8 = 7;
+
+fn write_varint(mut x: u64, out: &mut Vec<u8>) {
+    while x >= 0x80 {
+        out.push(x as u8 | 0x80);
+        x >>= 7
+    }
+    out.push(x as u8)
+}
+
+fn compact_string(x: &LocalStr, strings: &mut FnvHashMap<LocalStr, usize>, out: &mut Vec<u8>) {
+    if let Some(i) = strings.get(x) {
+        out.push(C_REF);
+        return write_varint(*i as u64, out);
+    }
+    strings.insert(x.clone(), strings.len());
+    // Treat anything unrepresentable in CC's charset as '?'.
+    let data = Vec::from_iter(x.chars().map(|i| u8::try_from(u32::from(i)).unwrap_or(b'?')));
+    out.push(C_STR);
+    write_varint(data.len() as u64, out);
+    out.extend_from_slice(&data)
+}
+
+fn compact_num(x: NotNan<f64>, out: &mut Vec<u8>) {
+    if *x >= 0. && x.fract() == 0. && *x < 9007199254740992. {
+        out.push(C_UINT);
+        write_varint(x.into_inner() as u64, out)
+    } else {
+        let data = x.to_string();
+        out.push(C_NUM);
+        write_varint(data.len() as u64, out);
+        out.extend_from_slice(data.as_bytes())
+    }
+}
+
+fn compact_bool(x: bool, out: &mut Vec<u8>) { out.push(if x { C_TRUE } else { C_FALSE }) }
+
+fn compact_value(x: &Value, strings: &mut FnvHashMap<LocalStr, usize>, out: &mut Vec<u8>) {
+    match x {
+        Value::N => out.push(C_NIL),
+        Value::F(x) => compact_num(*x, out),
+        Value::S(x) => compact_string(x, strings, out),
+        Value::B(x) => compact_bool(*x, out),
+        Value::T(x) => {
+            out.push(C_TABLE);
+            for (k, v) in x.iter() {
+                match k {
+                    Key::F(k) => compact_num(*k, out),
+                    Key::S(k) => compact_string(k, strings, out),
+                    Key::B(k) => compact_bool(*k, out),
+                }
+                compact_value(v, strings, out)
+            }
+            out.push(C_NIL)
+        }
+    }
+}
+
+pub fn serialize_compact(x: &Value, out: &mut Vec<u8>) { compact_value(x, &mut FnvHashMap::default(), out) }
+
+pub fn serialize_versioned(protocol: u8, x: &Value, out: &mut Vec<u8>) {
+    if protocol >= PROTOCOL_COMPACT {
+        serialize_compact(x, out)
+    } else {
+        serialize(x, out)
+    }
+}
+
 enum State {
     V,
     T { result: Table, key: Option<Key> },
     F(String),
     S { result: String, escape: bool },
+    // Compact encoding: a varint following `tag`, then for `C_STR` and `C_NUM` that many bytes.
+    Varint { tag: u8, value: u64, shift: u32 },
+    Bytes { tag: u8, result: Vec<u8>, len: usize },
 }
 
 pub struct Parser {
     stack: Vec<State>,
+    compact: bool,
+    // Strings of the current top-level value in the compact encoding.
+    strings: Vec<LocalStr>,
 }
 
 impl Parser {
-    pub fn new() -> Self { Parser { stack: vec![State::V] } }
+    pub fn new() -> Self { Parser { stack: vec![State::V], compact: false, strings: Vec::new() } }
+
+    // Only valid between top-level values.
+    pub fn set_protocol(&mut self, protocol: u8) { self.compact = protocol >= PROTOCOL_COMPACT }
+
+    fn reduce_bytes<T>(&mut self, tag: u8, result: Vec<u8>, handler: &mut T) -> Result<(), LocalStr>
+    where
+        T: FnMut(Value) -> Result<(), LocalStr>,
+    {
+        if tag == C_STR {
+            // Code-point can approximate CC's custom charset.
+            let result: LocalStr = result.into_iter().map(char::from).collect::<String>().into();
+            self.strings.push(result.clone());
+            self.reduce(result.into(), handler)
+        } else {
+            let result = String::from_utf8(result).map_err(|e| local_fmt!("invalid number: {}", e))?;
+            self.reduce(Value::F(result.parse().map_err(|e| local_fmt!("invalid number: {}", e))?), handler)
+        }
+    }
+
+    fn reduce_varint<T>(&mut self, tag: u8, value: u64, handler: &mut T) -> Result<(), LocalStr>
+    where
+        T: FnMut(Value) -> Result<(), LocalStr>,
+    {
+        match tag {
+            C_UINT => self.reduce(Value::F(NotNan::new(value as f64).unwrap()), handler),
+            C_REF => {
+                let x = self.strings.get(value as usize).ok_or_else(|| local_fmt!("invalid string ref: {}", value))?;
+                self.reduce(x.clone().into(), handler)
+            }
+            _ if value == 0 => self.reduce_bytes(tag, Vec::new(), handler),
+            _ => {
+                let len = usize::try_from(value).map_err(|_| local_fmt!("invalid length: {}", value))?;
+                self.stack.push(State::Bytes { tag, result: Vec::with_capacity(len.min(65536)), len });
+                Ok(())
+            }
+        }
+    }
 
     fn reduce<T>(&mut self, mut value: Value, handler: &mut T) -> Result<(), LocalStr>
     where
@@ -250,6 +376,7 @@ impl Parser {
         loop {
             match self.stack.pop() {
                 None => {
+                    self.strings.clear();
                     handler(value)?;
                     self.stack.push(State::V)
                 }
@@ -283,6 +410,48 @@ impl Parser {
     {
         'outer: while data.len() > 0 {
             match self.stack.pop().unwrap() {
+                State::V if self.compact => {
+                    let (x, rem) = data.split_first().unwrap();
+                    data = rem;
+                    match *x {
+                        C_NIL => self.reduce(Value::N, handler)?,
+                        C_FALSE => self.reduce(Value::B(false), handler)?,
+                        C_TRUE => self.reduce(Value::B(true), handler)?,
+                        C_NUM | C_UINT | C_STR | C_REF => {
+                            self.stack.push(State::Varint { tag: *x, value: 0, shift: 0 })
+                        }
+                        C_TABLE => {
+                            self.stack.push(State::T { result: Table::new(), key: None });
+                            self.stack.push(State::V)
+                        }
+                        x => return Err(local_fmt!("invalid tag: {}", x)),
+                    }
+                }
+                State::Varint { tag, mut value, mut shift } => {
+                    while let Some((x, rem)) = data.split_first() {
+                        data = rem;
+                        if shift > 63 {
+                            return Err(local_fmt!("varint too long"));
+                        }
+                        value |= u64::from(x & 0x7F) << shift;
+                        shift += 7;
+                        if x & 0x80 == 0 {
+                            self.reduce_varint(tag, value, handler)?;
+                            continue 'outer;
+                        }
+                    }
+                    self.stack.push(State::Varint { tag, value, shift })
+                }
+                State::Bytes { tag, mut result, len } => {
+                    let n = (len - result.len()).min(data.len());
+                    result.extend_from_slice(&data[..n]);
+                    data = &data[n..];
+                    if result.len() == len {
+                        self.reduce_bytes(tag, result, handler)?
+                    } else {
+                        self.stack.push(State::Bytes { tag, result, len })
+                    }
+                }
                 State::V => {
                     let (x, rem) = data.split_first().unwrap();
                     data = rem;
@@ -345,3 +514,95 @@ impl Parser {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f(x: f64) -> Value { Value::F(NotNan::new(x).unwrap()) }
+
+    fn parse_compact(data: &[u8], chunk_size: usize) -> Vec<Value> {
+        let mut parser = Parser::new();
+        parser.set_protocol(PROTOCOL_COMPACT);
+        let mut result = Vec::new();
+        for chunk in data.chunks(chunk_size) {
+            parser
+                .shift(chunk, &mut |x| {
+                    result.push(x);
+                    Ok(())
+                })
+                .unwrap()
+        }
+        result
+    }
+
+    fn round_trip(values: &[Value]) {
+        let mut data = Vec::new();
+        for x in values {
+            serialize_compact(x, &mut data)
+        }
+        // Byte by byte, so that every varint and string crosses a chunk boundary.
+        for chunk_size in [1, 2, 3, data.len()] {
+            assert_eq!(parse_compact(&data, chunk_size), values)
+        }
+    }
+
+    fn item(name: &'static str, count: i32) -> Value {
+        Table::from([("name".into(), name.into()), ("count".into(), count.into())]).into()
+    }
+
+    #[test]
+    fn scalars() {
+        round_trip(&[Value::N, true.into(), false.into(), "".into(), "a".into()]);
+        round_trip(&[0.into(), 1.into(), 127.into(), 128.into(), 300.into(), i64::from(u32::MAX).into()]);
+        round_trip(&[f(9007199254740991.), f(9007199254740992.), f(1e300)]);
+        round_trip(&[(-1).into(), f(-0.5), f(0.1), f(1.5), f(-1e-300), i64::MIN.into()]);
+        round_trip(&[LocalStr::from("x".repeat(300)).into()])
+    }
+
+    #[test]
+    fn nested_tables() {
+        let inner = Table::from([(1.into(), item("minecraft:stone", 64)), (2.into(), Table::new().into())]);
+        let outer = Table::from([
+            ("list".into(), inner.into()),
+            (Key::B(true), f(-2.5)),
+            (Key::F(NotNan::new(0.5).unwrap()), "half".into()),
+            (Key::F(NotNan::new(-3.).unwrap()), Table::new().into()),
+        ]);
+        round_trip(&[outer.into(), Table::new().into()])
+    }
+
+    #[test]
+    fn string_refs() {
+        let list = vec_to_table(vec![
+            item("minecraft:cobblestone", 64),
+            item("minecraft:cobblestone", 12),
+            item("minecraft:stone", 1),
+            "minecraft:stone".into(),
+        ]);
+        let mut data = Vec::new();
+        serialize_compact(&list.clone().into(), &mut data);
+        // Each distinct string, keys included, is sent once.
+        let n_sent = |x: &str| data.windows(x.len()).filter(|w| *w == x.as_bytes()).count();
+        assert_eq!(n_sent("minecraft:cobblestone"), 1);
+        assert_eq!(n_sent("minecraft:stone"), 1);
+        assert_eq!(n_sent("count"), 1);
+        assert!(data.contains(&C_REF));
+        // References are numbered per top-level value, so the second one sends its strings again.
+        round_trip(&[list.clone().into(), list.into(), "minecraft:stone".into()])
+    }
+
+    #[test]
+    fn unrepresentable_chars() {
+        let mut data = Vec::new();
+        serialize_compact(&"a\u{2603}b".into(), &mut data);
+        assert_eq!(parse_compact(&data, 1), [Value::from("a?b")])
+    }
+
+    #[test]
+    fn invalid_refs() {
+        let mut parser = Parser::new();
+        parser.set_protocol(PROTOCOL_COMPACT);
+        assert!(parser.shift(&[C_REF, 0], &mut |_| Ok(())).is_err())
+    }
+}
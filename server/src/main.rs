@@ -9,12 +9,18 @@ pub mod config_util;
 pub mod access;
 pub mod action;
 pub mod config;
+pub mod config_file;
 pub mod detail_cache;
 pub mod factory;
+pub mod history;
+pub mod http_api;
 pub mod item;
+pub mod journal;
 pub mod lua_value;
+pub mod planner;
 pub mod process;
 pub mod server;
+pub mod sim;
 pub mod storage;
 pub mod turtle_rc;
 
@@ -42,6 +48,11 @@ use std::{
 use tokio::{select, sync::Notify, task::LocalSet};
 use tui_textarea::{CursorMove, Input, Key, TextArea};
 
+fn get_command_args<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let args = line.trim_start().strip_prefix(name)?;
+    (args.is_empty() || args.starts_with(' ')).then(|| args.trim())
+}
+
 #[derive(Default)]
 pub struct Tui {
     on_redraw: Notify,
@@ -71,6 +82,24 @@ impl Tui {
         self.request_redraw()
     }
 
+    fn take_input(&self, pred: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut result = Vec::new();
+        self.input_queue.borrow_mut().retain(|line| {
+            if pred(line) {
+                result.push(line.clone());
+                return false;
+            }
+            true
+        });
+        result
+    }
+
+    // Removes input lines whose first word is `name` and returns the remainder of each.
+    fn take_commands(&self, name: &str) -> Vec<String> {
+        let lines = self.take_input(|line| get_command_args(line, name).is_some());
+        Vec::from_iter(lines.iter().filter_map(|line| get_command_args(line, name)).map(|x| x.to_owned()))
+    }
+
     fn set_main_list(&self, list: Vec<Line<'static>>) {
         *self.main_list.borrow_mut() = list;
         self.set_main_scroll(|x| x)
@@ -123,8 +152,23 @@ async fn main() {
         let mut term = Terminal::new(CrosstermBackend::new(std::io::stderr())).unwrap();
         let tui = Rc::<Tui>::default();
         // To run turtle_rc, replace with:
-        // let _factory = turtle_rc::run(server::Server::new(tui.clone(), 1848));
-        let _factory = build_factory(tui.clone());
+        // let _factory = turtle_rc::run(server::Server::new(tui.clone(), 1848, None));
+        // Pass a JSON layout file as an argument to use it instead of config.rs,
+        // and `--sim world.json` to connect simulated clients instead of ComputerCraft.
+        let mut args = std::env::args().skip(1);
+        let (mut layout, mut world) = (None, None);
+        while let Some(arg) = args.next() {
+            if arg == "--sim" {
+                world = args.next()
+            } else {
+                layout = Some(arg)
+            }
+        }
+        let _factory = match layout {
+            Some(path) => config_file::build_factory(tui.clone(), &path),
+            None => Some(build_factory(tui.clone())),
+        };
+        let _sim = world.and_then(|path| sim::run(tui.clone(), &path));
         loop {
             term.draw(|frame| tui.frame(frame)).unwrap();
             let evt = select! {
@@ -0,0 +1,220 @@
+use super::factory::Factory;
+use super::item::{Filter, Item};
+use super::recipe::{PlanInput, PlanRecipe, Product};
+use flexstr::{local_fmt, LocalStr};
+use fnv::FnvHashMap;
+use regex::Regex;
+use std::{mem::take, rc::Rc};
+
+pub struct PlanStep {
+    pub process: LocalStr,
+    pub product: Filter,
+    pub n_sets: i32,
+    pub depth: usize,
+    // All inputs are in storage now, as opposed to waiting for earlier steps.
+    pub is_ready: bool,
+}
+
+#[derive(Default)]
+pub struct PlanResult {
+    // In dependency order: every step comes after the steps producing its inputs.
+    pub steps: Vec<PlanStep>,
+    pub missing: Vec<(Filter, i32)>,
+    // Some count didn't fit in an i32, so the steps are incomplete.
+    pub overflow: bool,
+}
+
+// Expands requests into the full tree of intermediate crafts.
+// Stock claimed by one request (including leftovers of rounded-up sets) is unavailable to the next.
+pub struct Planner<'a> {
+    factory: &'a Factory,
+    recipes: &'a [PlanRecipe],
+    claimed: FnvHashMap<Rc<Item>, i32>,
+    surplus: Vec<(Filter, i32)>,
+    expanding: Vec<usize>,
+    result: PlanResult,
+}
+
+struct Snapshot {
+    claimed: FnvHashMap<Rc<Item>, i32>,
+    surplus: Vec<(Filter, i32)>,
+    n_steps: usize,
+    n_missing: usize,
+}
+
+impl<'a> Planner<'a> {
+    pub fn new(factory: &'a Factory, recipes: &'a [PlanRecipe]) -> Self {
+        Self {
+            factory,
+            recipes,
+            claimed: FnvHashMap::default(),
+            surplus: Vec::new(),
+            expanding: Vec::new(),
+            result: PlanResult::default(),
+        }
+    }
+
+    // Plans for `n_wanted` of `target` in total, counting what is already stored.
+    pub fn plan(&mut self, target: &Filter, n_wanted: i32) -> PlanResult {
+        let input = PlanInput { item: target.clone(), size: n_wanted, allow_backup: true, extra_backup: 0 };
+        let snapshot = self.snapshot();
+        self.expand(&input, n_wanted, 0);
+        if self.result.overflow {
+            // Nothing is claimed for a plan that gets dropped.
+            self.restore(snapshot);
+        }
+        take(&mut self.result)
+    }
+
+    // Same semantics as `resolve_inputs`: backups are excluded unless allowed, and `extra_backup` is kept.
+    fn claim_stored(&mut self, input: &PlanInput, mut n: i32) -> i32 {
+        if let Some((item, info)) = self.factory.search_item(&input.item) {
+            let claimed = self.claimed.entry(item.clone()).or_default();
+            let n_available = info.borrow().get_availability(input.allow_backup, input.extra_backup) - *claimed;
+            let n_claimed = n_available.max(0).min(n);
+            *claimed += n_claimed;
+            n -= n_claimed
+        }
+        n
+    }
+
+    fn claim_surplus(&mut self, input: &PlanInput, mut n: i32) -> i32 {
+        for (item, n_surplus) in &mut self.surplus {
+            if n > 0 && *item == input.item {
+                let n_claimed = (*n_surplus).min(n);
+                *n_surplus -= n_claimed;
+                n -= n_claimed
+            }
+        }
+        n
+    }
+
+    fn produces(&self, product: &Product, filter: &Filter) -> bool {
+        if let Some((item, info)) = self.factory.search_item(filter) {
+            if product.item.apply(item, &info.borrow().detail) {
+                return true;
+            }
+        }
+        product.item == *filter
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            claimed: self.claimed.clone(),
+            surplus: self.surplus.clone(),
+            n_steps: self.result.steps.len(),
+            n_missing: self.result.missing.len(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.claimed = snapshot.claimed;
+        self.surplus = snapshot.surplus;
+        self.result.steps.truncate(snapshot.n_steps);
+        self.result.missing.truncate(snapshot.n_missing)
+    }
+
+    // Returns whether `input` is fully covered by stored items.
+    fn expand(&mut self, input: &PlanInput, n: i32, depth: usize) -> bool {
+        let n = self.claim_stored(input, n);
+        if n <= 0 {
+            return true;
+        }
+        let n = self.claim_surplus(input, n);
+        if n <= 0 {
+            return false;
+        }
+        let mut candidates = Vec::new();
+        for (i_recipe, recipe) in self.recipes.iter().enumerate() {
+            if self.expanding.contains(&i_recipe) {
+                continue;
+            }
+            // A product yielding nothing per set can't be planned for.
+            if let Some(i_product) =
+                recipe.products.iter().position(|x| x.n_per_set > 0 && self.produces(x, &input.item))
+            {
+                candidates.push((i_recipe, i_product))
+            }
+        }
+        let Some(&first) = candidates.first() else {
+            self.result.missing.push((input.item.clone(), n));
+            return false;
+        };
+        // Prefer the first recipe that can be fully satisfied; otherwise report what the first one lacks.
+        for &(i_recipe, i_product) in &candidates {
+            let snapshot = self.snapshot();
+            self.expand_recipe(i_recipe, i_product, n, depth);
+            if self.result.missing.len() == snapshot.n_missing {
+                return false;
+            }
+            self.restore(snapshot)
+        }
+        self.expand_recipe(first.0, first.1, n, depth);
+        false
+    }
+
+    fn expand_recipe(&mut self, i_recipe: usize, i_product: usize, n: i32, depth: usize) {
+        let recipe = &self.recipes[i_recipe];
+        let product = &recipe.products[i_product];
+        let Some(n_sets) = n.checked_add(product.n_per_set - 1).map(|x| x / product.n_per_set) else {
+            self.result.overflow = true;
+            return;
+        };
+        for (i, x) in recipe.products.iter().enumerate() {
+            let Some(n_produced) = x.n_per_set.checked_mul(n_sets) else {
+                self.result.overflow = true;
+                return;
+            };
+            let n_surplus = n_produced - if i == i_product { n } else { 0 };
+            if n_surplus > 0 {
+                self.surplus.push((x.item.clone(), n_surplus))
+            }
+        }
+        self.expanding.push(i_recipe);
+        let mut is_ready = true;
+        for input in &recipe.inputs {
+            let Some(n_input) = input.size.checked_mul(n_sets) else {
+                self.result.overflow = true;
+                break;
+            };
+            is_ready &= self.expand(input, n_input, depth + 1)
+        }
+        self.expanding.pop();
+        let step = PlanStep { process: recipe.process.clone(), product: product.item.clone(), n_sets, depth, is_ready };
+        self.result.steps.push(step)
+    }
+}
+
+// Keeps the planner's counts (sets times inputs per set, etc.) well within an i32.
+const MAX_CRAFT_COUNT: i32 = 1_000_000;
+
+// Parses `<label|=name> <count>` of the `craft` command. The label or name is a regex matching the whole string.
+// Recipe products are tried first so that items not yet in storage can be requested.
+pub fn parse_craft_command(factory: &Factory, recipes: &[PlanRecipe], args: &str) -> Result<(Filter, i32), LocalStr> {
+    let usage = || local_fmt!("usage: craft <label|=name> <count up to {}>", MAX_CRAFT_COUNT);
+    let (needle, n) = args.rsplit_once(' ').ok_or_else(usage)?;
+    let n: i32 = n.parse().ok().filter(|&n| n > 0 && n <= MAX_CRAFT_COUNT).ok_or_else(usage)?;
+    let (needle, by_name) = match needle.trim().strip_prefix('=') {
+        Some(needle) => (needle, true),
+        None => (needle.trim(), false),
+    };
+    let regex = Regex::new(&format!("^(?:{needle})$")).map_err(|e| local_fmt!("invalid regex: {}", e))?;
+    for product in recipes.iter().flat_map(|x| &x.products) {
+        let is_match = match &product.item {
+            Filter::Label(label) => !by_name && regex.is_match(label),
+            Filter::Name(name) => by_name && regex.is_match(name),
+            Filter::Both { label, name } => regex.is_match(if by_name { name } else { label }),
+            Filter::Custom { .. } => false,
+        };
+        if is_match {
+            return Ok((product.item.clone(), n));
+        }
+    }
+    for (item, info) in &factory.items {
+        let label = &info.borrow().detail.label;
+        if regex.is_match(if by_name { &item.name } else { label }) {
+            return Ok((Filter::Both { label: label.clone(), name: item.name.clone() }, n));
+        }
+    }
+    Err(local_fmt!("nothing matches {}", needle))
+}
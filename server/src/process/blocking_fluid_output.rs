@@ -2,7 +2,7 @@ use super::super::factory::Factory;
 use super::super::inventory::Inventory;
 use super::super::util::{alive, join_tasks, spawn};
 use super::{IntoProcess, Process};
-use crate::access::TankAccess;
+use crate::access::{ExpectedPeripherals, TankAccess};
 use crate::action::{ActionFuture, Call};
 use crate::recipe::FluidOutput;
 use abort_on_drop::ChildTask;
@@ -37,6 +37,10 @@ impl IntoProcess for BlockingFluidOutputConfig {
 }
 
 impl Process for BlockingFluidOutputProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add("blocking fluid output", &self.config.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let mut tasks = Vec::new();
         for output in &self.config.outputs {
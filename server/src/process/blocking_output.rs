@@ -1,4 +1,4 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
@@ -41,6 +41,10 @@ struct Info {
 }
 
 impl Process for BlockingOutputProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add("blocking output", &self.config.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let mut enough = true;
         for output in &self.config.outputs {
@@ -1,10 +1,12 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::action::{ActionFuture, Call};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{insert_into_inventory, jammer, Detail, Filter, InsertPlan, Item};
-use super::super::recipe::{compute_demands, resolve_inputs, Demand, Input, Outputs, Recipe};
+use super::super::recipe::{
+    compute_demands, get_plan_recipes, resolve_inputs, Demand, Input, Outputs, PlanRecipe, Recipe,
+};
 use super::super::server::Server;
 use super::super::util::{alive, join_outputs, join_tasks, spawn};
 use super::{extract_output, scattering_insert, ExtractFilter, IntoProcess, Process, SlotFilter};
@@ -62,6 +64,10 @@ impl_inventory!(BufferedProcess, BusAccess);
 impl_into_process!(BufferedConfig, BufferedProcess);
 
 impl Process for BufferedProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         if self.config.to_extract.is_none() && self.config.stocks.is_empty() {
             if compute_demands(factory, &self.config.recipes).is_empty() {
@@ -167,6 +173,7 @@ impl Process for BufferedProcess {
                                 *existing_size.get_mut(item).unwrap() += plans[i_input].n_inserted
                             }
                             remaining_size -= inputs.n_sets * size_per_set;
+                            factory.report_demand(&this.config.name, i_recipe, &*recipe.outputs, inputs.n_sets);
                             tasks.push(this.execute_recipe(factory, inputs.items, plans));
                             if remaining_size <= 0 {
                                 break 'recipe;
@@ -178,6 +185,8 @@ impl Process for BufferedProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.config.name, &self.config.recipes) }
 }
 
 impl BufferedProcess {
@@ -1,8 +1,11 @@
-use super::super::access::CraftyAccess;
+use super::super::access::{CraftyAccess, ExpectedPeripherals};
 use super::super::action::{ActionFuture, Call, TurtleCall};
 use super::super::factory::Factory;
 use super::super::inventory::Inventory;
-use super::super::recipe::{compute_demands, resolve_inputs, CraftingGridRecipe, ResolvedInputs};
+use super::super::journal::Undo;
+use super::super::recipe::{
+    compute_demands, get_plan_recipes, resolve_inputs, CraftingGridRecipe, PlanRecipe, ResolvedInputs,
+};
 use super::super::util::{alive, join_outputs, join_tasks, spawn};
 use super::{IntoProcess, Process};
 use abort_on_drop::ChildTask;
@@ -66,6 +69,7 @@ impl CraftyProcess {
             upgrade_mut!(self.factory, factory);
             if let Some(ResolvedInputs { mut n_sets, items, .. }) = resolve_inputs(factory, recipe) {
                 n_sets = n_sets.min(recipe.max_sets);
+                factory.report_demand(&self.config.name, i_recipe, &*recipe.outputs, n_sets);
                 let mut bus_slots = Vec::new();
                 let slots_to_free = Rc::new(RefCell::new(Vec::new()));
                 for (i_input, (item, _)) in items.into_iter().enumerate() {
@@ -172,6 +176,33 @@ impl CraftyProcess {
         group.into_iter().map(|x| spawn(async move { x.await.map(|_| ()) })).collect()
     }
 
+    // On failure or restart, non-consumables go back to their storage slots and the rest of the grid to storage.
+    fn journal_begin(&self, job: &JobRef) -> Option<usize> {
+        upgrade!(self.factory, factory);
+        let server = factory.get_server().borrow();
+        let access = server.load_balance(&self.config.turtles[job.i_turtle].accesses);
+        let mut undo = Vec::new();
+        for non_consumable in &self.config.recipes[job.i_recipe].non_consumables {
+            undo.push(Undo::Move {
+                client: access.client.to_std_string(),
+                addr: access.non_consumable_addr.to_std_string(),
+                method: "pullItems".to_owned(),
+                other: access.turtle_addr.to_std_string(),
+                from_slot: map_turtle_grid(non_consumable.crafting_grid_slot),
+                to_slot: non_consumable.storage_slot,
+            })
+        }
+        for slot in 0..9 {
+            undo.push(Undo::Return {
+                client: access.client.to_std_string(),
+                bus_addr: access.bus_addr.to_std_string(),
+                inv_addr: access.turtle_addr.to_std_string(),
+                slot: map_turtle_grid(slot),
+            })
+        }
+        factory.journal_begin(&self.config.name, undo)
+    }
+
     fn initial_cleanup(&self, i_turtle: usize) -> impl Future<Output = Result<(), LocalStr>> {
         upgrade_mut!(self.factory, factory);
         let slots_to_free = Rc::new(RefCell::new(Vec::new()));
@@ -225,14 +256,20 @@ async fn worker_main(weak: Weak<RefCell<CraftyProcess>>, i_turtle: usize) -> Res
         let task = async {
             let bus_slots = bus_slots?;
             let job = JobRef { i_recipe, i_turtle, n_sets, bus_slots: &bus_slots };
-            let tasks = alive(&weak)?.borrow().load_inputs(&job);
-            join_tasks(tasks).await?;
-            let action = alive(&weak)?.borrow().craft(&job);
-            action.await?;
-            let tasks = alive(&weak)?.borrow().store_outputs(&job, slots_to_free[0]);
-            join_tasks(tasks).await?;
+            let journal = alive(&weak)?.borrow().journal_begin(&job);
+            let result = async {
+                let tasks = alive(&weak)?.borrow().load_inputs(&job);
+                join_tasks(tasks).await?;
+                let action = alive(&weak)?.borrow().craft(&job);
+                action.await?;
+                let tasks = alive(&weak)?.borrow().store_outputs(&job, slots_to_free[0]);
+                join_tasks(tasks).await
+            }
+            .await;
             alive!(weak, this);
             upgrade_mut!(this.factory, factory);
+            factory.journal_end(journal, &result);
+            result?;
             while slots_to_free.len() > 1 {
                 factory.bus_free(slots_to_free.pop().unwrap())
             }
@@ -263,6 +300,12 @@ impl IntoProcess for CraftyConfig {
 }
 
 impl Process for CraftyProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        for turtle in &self.config.turtles {
+            expected.add(&self.config.name, &turtle.accesses)
+        }
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let jobs = compute_demands(factory, &self.config.recipes).into_iter().map(|x| x.i_recipe).collect();
         let weak = self.weak.clone();
@@ -275,4 +318,6 @@ impl Process for CraftyProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.config.name, &self.config.recipes) }
 }
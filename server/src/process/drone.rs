@@ -1,4 +1,4 @@
-use super::super::access::BasicAccess;
+use super::super::access::{BasicAccess, ExpectedPeripherals};
 use super::super::action::Log;
 use super::super::action::{ActionFuture, Call};
 use super::super::factory::Factory;
@@ -185,6 +185,8 @@ impl<State: Serialize + for<'a> Deserialize<'a>, Task: Future<Output = ()> + 'st
 }
 
 impl Process for DroneProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add(&self.name, &self.accesses) }
+
     fn run(&self, _: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let weak = self.weak.clone();
         spawn(async move {
@@ -1,12 +1,12 @@
 use super::{EachInv, EachInvConfig, IntoProcess, MultiInvExtractFilter, MultiInvSlottedInput, Process};
 use crate::{
-    access::{BusAccess, InvTankAccess},
+    access::{BusAccess, ExpectedPeripherals, InvTankAccess},
     action::{ActionFuture, Call},
     factory::{read_tanks, tanks_to_fluid_map, Factory},
     inventory::{list_inventory, Inventory},
     item::DetailStack,
     process::extract_output,
-    recipe::{resolve_inputs, Demand, Outputs, Recipe},
+    recipe::{get_demand_priority, get_plan_recipes, resolve_inputs, Demand, Outputs, PlanRecipe, Recipe},
     server::Server,
     util::{alive, join_outputs, join_tasks, spawn},
 };
@@ -150,7 +150,7 @@ struct InputInfo {
 fn compute_fluid_demands(factory: &Factory, recipes: &[FluidSlottedRecipe]) -> Vec<Demand> {
     let mut result = Vec::new();
     for (i_recipe, recipe) in recipes.iter().enumerate() {
-        let Some(mut priority) = recipe.get_outputs().get_priority(factory) else { continue };
+        let Some(mut priority) = get_demand_priority(factory, recipe) else { continue };
         let Some(mut inputs) = resolve_inputs(factory, recipe) else { continue };
         let mut infos = FnvHashMap::<LocalStr, InputInfo>::default();
         let mut bus_bound = i64::MAX;
@@ -187,6 +187,8 @@ fn compute_fluid_demands(factory: &Factory, recipes: &[FluidSlottedRecipe]) -> V
 }
 
 impl Process for FluidSlottedProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add(&self.name, &self.accesses) }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         if self.to_extract.is_none()
             && self.fluid_extract.is_none()
@@ -301,6 +303,8 @@ impl Process for FluidSlottedProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.name, &self.recipes) }
 }
 
 impl FluidSlottedProcess {
@@ -351,6 +355,7 @@ impl FluidSlottedProcess {
         let mut fluid_buses = Vec::new();
         let fluid_buses_to_free = Rc::new(RefCell::new(Vec::new()));
         let recipe = &self.recipes[demand.i_recipe];
+        factory.report_demand(&self.name, demand.i_recipe, &*recipe.outputs, demand.inputs.n_sets);
         for (i_input, input) in recipe.inputs.iter().enumerate() {
             let reservation =
                 factory.reserve_item(&self.name, &demand.inputs.items[i_input].0, demand.inputs.n_sets * input.size);
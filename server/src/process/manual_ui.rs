@@ -1,8 +1,9 @@
 use super::{scattering_insert, IntoProcess, Inventory, Process};
+use crate::access::{BusAccess, ExpectedPeripherals};
 use crate::inventory::list_inventory;
 use crate::item::{insert_into_inventory, InsertPlan};
 use crate::util::{alive, join_tasks, spawn};
-use crate::{access::BusAccess, detail_cache::DetailCache, factory::Factory, item::DetailStack, server::Server, Tui};
+use crate::{detail_cache::DetailCache, factory::Factory, item::DetailStack, server::Server, Tui};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
 use futures_util::future::OptionFuture;
@@ -26,6 +27,7 @@ pub struct ManualUiProcess {
     server: Rc<RefCell<Server>>,
     size: Option<usize>,
     latest_view: Vec<DetailStack>,
+    plan_view: Vec<Line<'static>>,
     _input_handler: ChildTask<()>,
 }
 
@@ -45,6 +47,7 @@ impl IntoProcess for ManualUiConfig {
                 server: factory.get_server().clone(),
                 size: None,
                 latest_view: Vec::new(),
+                plan_view: Vec::new(),
                 _input_handler: spawn(async move { input_handler(tui, weak).await }),
             })
         })
@@ -72,6 +75,41 @@ fn make_pred(needle: &str) -> Box<dyn Fn(&DetailStack) -> bool> {
     }
 }
 
+fn make_plan_view(factory: &Factory) -> Vec<Line<'static>> {
+    let mut result = Vec::new();
+    for plan in &factory.plans {
+        let (status, color) = if plan.is_done {
+            (String::from("done"), Color::LightGreen)
+        } else if !plan.result.missing.is_empty() {
+            let missing = Vec::from_iter(plan.result.missing.iter().map(|(x, n)| format!("{}*{}", x.describe(), n)));
+            (format!("blocked on {}", missing.join(", ")), Color::LightRed)
+        } else if plan.result.steps.iter().any(|x| x.is_ready) {
+            (String::from("in progress"), Color::LightYellow)
+        } else {
+            (String::from("queued"), Color::Gray)
+        };
+        result.push(Line::from(vec![
+            Span::raw(format!("{}/{} * ", factory.search_n_stored(&plan.target), plan.n_wanted)),
+            Span::styled(format!("{} ", plan.target.describe()), Color::LightCyan),
+            Span::styled(status, color),
+        ]));
+        for step in &plan.result.steps {
+            let state = if step.is_ready { "ready" } else { "waiting" };
+            result.push(Line::styled(
+                format!(
+                    "{}{} -> {} x{} ({state})",
+                    "  ".repeat(step.depth + 1),
+                    step.process,
+                    step.product.describe(),
+                    step.n_sets
+                ),
+                Style::from(Color::Gray).add_modifier(Modifier::DIM),
+            ))
+        }
+    }
+    result
+}
+
 impl ManualUiProcess {
     fn update_view(&self, tui: &Tui) {
         let text_area = tui.text_area.borrow();
@@ -81,14 +119,14 @@ impl ManualUiProcess {
         }
         let pred = make_pred(needle);
         tui.set_main_list(
-            (self.latest_view.iter().filter(|x| pred(x)))
-                .map(|x| {
+            (self.plan_view.iter().cloned())
+                .chain(self.latest_view.iter().filter(|x| pred(x)).map(|x| {
                     Line::from(vec![
                         Span::raw(format!("{} * ", x.size)),
                         Span::styled(format!("{} ", x.detail.label), Color::LightGreen),
                         Span::styled(x.item.name.to_std_string(), Style::from(Color::Gray).add_modifier(Modifier::DIM)),
                     ])
-                })
+                }))
                 .collect(),
         );
         tui.request_redraw()
@@ -96,6 +134,10 @@ impl ManualUiProcess {
 }
 
 impl Process for ManualUiProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add("manual ui", &self.config.accesses)
+    }
+
     fn run(&self, _: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let stacks = (!self.config.accesses.is_empty()).then(|| list_inventory(self));
         let weak = self.weak.clone();
@@ -110,9 +152,10 @@ impl Process for ManualUiProcess {
                     DetailStack { item: item.clone(), detail: info.detail.clone(), size: info.n_stored }
                 }));
                 this.latest_view.sort_by_key(|x| -x.size);
+                this.plan_view = make_plan_view(factory);
                 let tui = factory.config.tui.clone();
                 this.update_view(&tui);
-                for request in tui.input_queue.borrow_mut().drain(..) {
+                for request in tui.take_input(|x| x.contains('*')) {
                     let Some(pos) = request.rfind('*') else { continue };
                     let pred = make_pred(&request[..pos]);
                     let Some(stack) = this.latest_view.iter().find(|x| pred(x)) else { continue };
@@ -1,10 +1,10 @@
-use super::super::access::{BusAccess, RedstoneAccess};
+use super::super::access::{BusAccess, ExpectedPeripherals, RedstoneAccess};
 use super::super::action::{ActionFuture, Call, Log, RedstoneInput, RedstoneOutput};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{insert_into_inventory, jammer, Filter, InsertPlan};
-use super::super::recipe::Input;
+use super::super::recipe::{Input, PlanRecipe};
 use super::super::server::Server;
 use super::super::util::{alive, join_tasks, spawn};
 use super::{extract_output, scattering_insert, BufferedInput, IntoProcess, Process, ScatteringInput};
@@ -37,6 +37,10 @@ impl<T: IntoProcess> IntoProcess for ConditionalConfig<T> {
 }
 
 impl<T: Process> Process for ConditionalProcess<T> {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        self.child.borrow().expect_peripherals(expected)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         if (self.condition)(factory) {
             self.child.borrow().run(factory)
@@ -44,6 +48,8 @@ impl<T: Process> Process for ConditionalProcess<T> {
             spawn(async { Ok(()) })
         }
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { self.child.borrow().get_recipes() }
 }
 
 pub struct SyncAndRestockConfig {
@@ -167,12 +173,19 @@ impl SyncAndRestockProcess {
 }
 
 impl Process for SyncAndRestockProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses);
+        expected.add(&self.config.name, &self.config.accesses_in);
+        expected.add(&self.config.name, &self.config.accesses_out)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let server = factory.get_server().borrow();
-        let access = server.load_balance(&self.config.accesses_in);
-        let action =
-            ActionFuture::from(RedstoneInput { side: access.side.clone(), addr: access.addr.clone(), bit: access.bit });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        let action = server.call_with_failover(&self.config.accesses_in, |access| RedstoneInput {
+            side: access.side.clone(),
+            addr: access.addr.clone(),
+            bit: access.bit,
+        });
         let weak = self.weak.clone();
         spawn(async move {
             let is_high = action.await? > 0;
@@ -220,12 +233,7 @@ pub struct LowAlert {
 
 impl LowAlert {
     pub fn new(item: Filter, n_wanted: i32) -> Self {
-        let log = match &item {
-            Filter::Label(x) => x.clone(),
-            Filter::Name(x) => local_fmt!("<{}>", x),
-            Filter::Both { label, name } => local_fmt!("{} <{}>", label, name),
-            Filter::Custom { desc, .. } => local_fmt!("<{}>", desc),
-        };
+        let log = item.describe();
         Self { item, n_wanted, log }
     }
 }
@@ -291,6 +299,10 @@ impl IntoProcess for ItemCycleConfig {
 impl_inventory!(ItemCycleProcess, BusAccess);
 
 impl Process for ItemCycleProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses)
+    }
+
     fn run(&self, _: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let stacks = list_inventory(self);
         let weak = self.weak.clone();
@@ -1,8 +1,9 @@
-use super::access::BusAccess;
+use super::access::{BusAccess, ExpectedPeripherals};
 use super::action::{ActionFuture, Call};
 use super::factory::{Factory, Reservation};
 use super::inventory::Inventory;
 use super::item::DetailStack;
+use super::recipe::PlanRecipe;
 use super::util::{alive, join_tasks, spawn};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
@@ -10,6 +11,8 @@ use std::{cell::RefCell, iter::once, rc::Rc};
 
 pub trait Process: 'static {
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>>;
+    fn get_recipes(&self) -> Vec<PlanRecipe> { Vec::new() }
+    fn expect_peripherals(&self, _: &mut ExpectedPeripherals) {}
 }
 
 pub trait IntoProcess {
@@ -22,6 +25,13 @@ impl<T: Process> IntoProcess for T {
     fn into_process(self, _: &Factory) -> Rc<RefCell<Self::Output>> { Rc::new(RefCell::new(self)) }
 }
 
+// Lets already-built processes (e.g. from config_file) be nested inside generic wrappers.
+impl Process for Rc<RefCell<dyn Process>> {
+    fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> { self.borrow().run(factory) }
+    fn get_recipes(&self) -> Vec<PlanRecipe> { self.borrow().get_recipes() }
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { self.borrow().expect_peripherals(expected) }
+}
+
 macro_rules! impl_into_process {
     ($c:ident, $p:ident) => {
         impl IntoProcess for $c {
@@ -136,6 +146,7 @@ mod fluid_slotted;
 mod manual_ui;
 mod misc;
 mod multi_inv_slotted;
+mod order;
 mod redstone;
 mod scattering;
 mod slotted;
@@ -150,6 +161,7 @@ pub use fluid_slotted::*;
 pub use manual_ui::*;
 pub use misc::*;
 pub use multi_inv_slotted::*;
+pub use order::*;
 pub use redstone::*;
 pub use scattering::*;
 pub use slotted::*;
@@ -1,11 +1,11 @@
-use super::super::access::{BusAccess, MultiInvAccess};
+use super::super::access::{BusAccess, ExpectedPeripherals, MultiInvAccess};
 use super::super::action::{ActionFuture, Call};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{DetailStack, Filter};
 use super::super::process::{IntoProcess, Process};
-use super::super::recipe::{compute_demands, Demand, Input, Outputs, Recipe};
+use super::super::recipe::{compute_demands, get_plan_recipes, Demand, Input, Outputs, PlanRecipe, Recipe};
 use super::super::server::Server;
 use super::super::util::{alive, join_outputs, join_tasks, spawn};
 use super::extract_output;
@@ -128,6 +128,8 @@ impl IntoProcess for MultiInvSlottedConfig {
 }
 
 impl Process for MultiInvSlottedProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add(&self.name, &self.accesses) }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         if self.to_extract.is_none() && compute_demands(factory, &self.recipes).is_empty() {
             return spawn(async { Ok(()) });
@@ -205,6 +207,8 @@ impl Process for MultiInvSlottedProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.name, &self.recipes) }
 }
 
 impl MultiInvSlottedProcess {
@@ -212,6 +216,7 @@ impl MultiInvSlottedProcess {
         let mut bus_slots = Vec::new();
         let slots_to_free = Rc::new(RefCell::new(Vec::new()));
         let recipe = &self.recipes[demand.i_recipe];
+        factory.report_demand(&self.name, demand.i_recipe, &*recipe.outputs, demand.inputs.n_sets);
         for (i_input, input) in recipe.inputs.iter().enumerate() {
             let reservation =
                 factory.reserve_item(&self.name, &demand.inputs.items[i_input].0, demand.inputs.n_sets * input.size);
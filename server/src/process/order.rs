@@ -0,0 +1,128 @@
+use super::{scattering_insert, Inventory, Process};
+use crate::access::{BusAccess, ExpectedPeripherals};
+use crate::action::Log;
+use crate::inventory::list_inventory;
+use crate::item::{insert_into_inventory, Filter, InsertPlan};
+use crate::util::{alive, join_tasks, spawn};
+use crate::{detail_cache::DetailCache, factory::Factory, server::Server};
+use abort_on_drop::ChildTask;
+use flexstr::{local_fmt, LocalStr};
+use std::{
+    cell::RefCell,
+    mem::take,
+    rc::{Rc, Weak},
+};
+
+// An inventory that remote clients can have items delivered to, see `Factory::add_order`.
+pub struct OrderOutputConfig {
+    pub name: LocalStr,
+    pub accesses: Vec<BusAccess>,
+    // Bearer tokens allowed to order into this inventory.
+    pub tokens: Vec<LocalStr>,
+}
+
+pub struct Order {
+    pub id: usize,
+    pub item: Filter,
+    pub n_wanted: i32,
+    pub n_delivered: i32,
+    // Inserted but not yet confirmed, so not counted in `n_delivered`.
+    pub n_in_flight: i32,
+}
+
+pub struct OrderOutputProcess {
+    weak: Weak<RefCell<OrderOutputProcess>>,
+    pub config: OrderOutputConfig,
+    detail_cache: Rc<RefCell<DetailCache>>,
+    factory: Weak<RefCell<Factory>>,
+    server: Rc<RefCell<Server>>,
+    size: Option<usize>,
+    pub orders: Vec<Order>,
+}
+
+impl_inventory!(OrderOutputProcess, BusAccess);
+
+impl OrderOutputProcess {
+    pub fn new(factory: &Factory, config: OrderOutputConfig) -> Rc<RefCell<Self>> {
+        Rc::new_cyclic(|weak| {
+            RefCell::new(Self {
+                weak: weak.clone(),
+                config,
+                detail_cache: factory.get_detail_cache().clone(),
+                factory: factory.get_weak().clone(),
+                server: factory.get_server().clone(),
+                size: None,
+                orders: Vec::new(),
+            })
+        })
+    }
+}
+
+impl Factory {
+    // Counts an insert into an order once it has finished, completing the order when all of it is delivered.
+    fn confirm_delivery(&self, output: &str, id: usize, n_inserted: i32, is_ok: bool) {
+        // The output may have been replaced by a reload since, in which case its orders have moved to the new one.
+        let Some(output) = self.get_order_output(output) else { return };
+        let mut output = output.borrow_mut();
+        let Some(i) = output.orders.iter().position(|x| x.id == id) else { return };
+        let order = &mut output.orders[i];
+        order.n_in_flight -= n_inserted;
+        if is_ok {
+            order.n_delivered += n_inserted
+        }
+        if order.n_delivered >= order.n_wanted {
+            let text = local_fmt!("order #{} done: {}*{}", order.id, order.item.describe(), order.n_wanted);
+            self.log(Log { text, color: 13 });
+            output.orders.remove(i);
+        }
+    }
+}
+
+// Orders are delivered as stock and space allow, and wait in the queue otherwise (e.g. for a `craft`).
+impl Process for OrderOutputProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses)
+    }
+
+    fn run(&self, _: &Factory) -> ChildTask<Result<(), LocalStr>> {
+        if self.orders.is_empty() {
+            return spawn(async { Ok(()) });
+        }
+        let stacks = list_inventory(self);
+        let weak = self.weak.clone();
+        spawn(async move {
+            let mut stacks = stacks.await?;
+            let mut tasks = Vec::new();
+            {
+                alive_mut!(weak, this);
+                upgrade_mut!(this.factory, factory);
+                let mut orders = take(&mut this.orders);
+                for order in &mut orders {
+                    let Some((item, info)) = factory.search_item(&order.item) else { continue };
+                    let (item, detail) = (item.clone(), info.borrow().detail.clone());
+                    let n_pending = order.n_wanted - order.n_delivered - order.n_in_flight;
+                    let mut size = info.borrow().n_stored.min(n_pending);
+                    loop {
+                        let InsertPlan { n_inserted, insertions } =
+                            insert_into_inventory(&mut stacks, &item, &detail, size.min(detail.max_size));
+                        if n_inserted <= 0 {
+                            break;
+                        }
+                        let reservation = factory.reserve_item(&this.config.name, &item, n_inserted);
+                        let task = scattering_insert(this, factory, reservation, insertions);
+                        let (factory, name, id) = (this.factory.clone(), this.config.name.clone(), order.id);
+                        tasks.push(spawn(async move {
+                            let result = task.await.unwrap();
+                            alive(&factory)?.borrow().confirm_delivery(&name, id, n_inserted, result.is_ok());
+                            result
+                        }));
+                        order.n_in_flight += n_inserted;
+                        size -= n_inserted
+                    }
+                }
+                this.orders = orders
+            }
+            join_tasks(tasks).await
+        })
+    }
+}
@@ -1,8 +1,8 @@
-use super::super::access::RedstoneAccess;
+use super::super::access::{ExpectedPeripherals, RedstoneAccess};
 use super::super::action::{ActionFuture, Log, RedstoneInput, RedstoneOutput};
 use super::super::factory::Factory;
 use super::super::inventory::Inventory;
-use super::super::recipe::Outputs;
+use super::super::recipe::{Outputs, PlanRecipe};
 use super::super::util::{alive, spawn};
 use super::{IntoProcess, Process};
 use abort_on_drop::ChildTask;
@@ -29,6 +29,10 @@ pub struct RedstoneEmitterConfig {
 }
 
 impl Process for RedstoneEmitterConfig {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add("redstone emitter", &self.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let value = (self.output)(factory);
         let server = factory.get_server().borrow();
@@ -75,12 +79,18 @@ impl<T: IntoProcess> IntoProcess for RedstoneConditionalConfig<T> {
 }
 
 impl<T: Process> Process for RedstoneConditionalProcess<T> {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(self.name.as_deref().unwrap_or("redstone conditional"), &self.accesses);
+        self.child.borrow().expect_peripherals(expected)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let server = factory.get_server().borrow();
-        let access = server.load_balance(&self.accesses);
-        let action =
-            ActionFuture::from(RedstoneInput { side: access.side.clone(), addr: access.addr.clone(), bit: access.bit });
-        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        let action = server.call_with_failover(&self.accesses, |access| RedstoneInput {
+            side: access.side.clone(),
+            addr: access.addr.clone(),
+            bit: access.bit,
+        });
         let weak = self.weak.clone();
         let factory = factory.get_weak().clone();
         spawn(async move {
@@ -100,4 +110,6 @@ impl<T: Process> Process for RedstoneConditionalProcess<T> {
             task.await.unwrap()
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { self.child.borrow().get_recipes() }
 }
@@ -1,9 +1,11 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{DetailStack, Filter};
-use super::super::recipe::{compute_demands, resolve_inputs, Demand, Input, Outputs, Recipe};
+use super::super::recipe::{
+    compute_demands, get_plan_recipes, resolve_inputs, Demand, Input, Outputs, PlanRecipe, Recipe,
+};
 use super::super::server::Server;
 use super::super::util::{alive, join_tasks, spawn};
 use super::{extract_output, scattering_insert, ExtractFilter, IntoProcess, Process};
@@ -66,6 +68,10 @@ impl_inventory!(ScatteringProcess, BusAccess);
 impl_into_process!(ScatteringConfig, ScatteringProcess);
 
 impl Process for ScatteringProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         if self.config.to_extract.is_none() && compute_demands(factory, &self.config.recipes).is_empty() {
             return spawn(async { Ok(()) });
@@ -131,6 +137,8 @@ impl Process for ScatteringProcess {
                             }
                         }
                         if n_inserted > 0 {
+                            let outputs = &*this.config.recipes[i_recipe].outputs;
+                            factory.report_demand(&this.config.name, i_recipe, outputs, n_inserted);
                             let reservation = factory.reserve_item(&this.config.name, &inputs.items[0].0, n_inserted);
                             tasks.push(scattering_insert(this, factory, reservation, insertions))
                         }
@@ -140,4 +148,6 @@ impl Process for ScatteringProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.config.name, &self.config.recipes) }
 }
@@ -1,10 +1,10 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::action::{ActionFuture, Call};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
 use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{DetailStack, Filter};
-use super::super::recipe::{compute_demands, Demand, Input, Outputs, Recipe};
+use super::super::recipe::{compute_demands, get_plan_recipes, Demand, Input, Outputs, PlanRecipe, Recipe};
 use super::super::server::Server;
 use super::super::util::{alive, join_outputs, join_tasks, spawn};
 use super::{extract_output, ExtractFilter, IntoProcess, Process};
@@ -65,6 +65,10 @@ impl_inventory!(SlottedProcess, BusAccess);
 impl_into_process!(SlottedConfig, SlottedProcess);
 
 impl Process for SlottedProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         if self.config.to_extract.is_none() && compute_demands(factory, &self.config.recipes).is_empty() {
             return spawn(async { Ok(()) });
@@ -132,6 +136,8 @@ impl Process for SlottedProcess {
             join_tasks(tasks).await
         })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.config.name, &self.config.recipes) }
 }
 
 impl SlottedProcess {
@@ -139,6 +145,7 @@ impl SlottedProcess {
         let mut bus_slots = Vec::new();
         let slots_to_free = Rc::new(RefCell::new(Vec::new()));
         let recipe = &self.config.recipes[demand.i_recipe];
+        factory.report_demand(&self.config.name, demand.i_recipe, &*recipe.outputs, demand.inputs.n_sets);
         for (i_input, input) in recipe.inputs.iter().enumerate() {
             let reservation = factory.reserve_item(
                 &self.config.name,
@@ -1,9 +1,11 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::action::{ActionFuture, Call};
 use super::super::factory::Factory;
 use super::super::inventory::Inventory;
+use super::super::journal::Undo;
 use super::super::recipe::{
-    compute_demands, resolve_inputs, CraftingGridRecipe, Demand, NonConsumable, ResolvedInputs,
+    compute_demands, get_plan_recipes, resolve_inputs, CraftingGridRecipe, Demand, NonConsumable, PlanRecipe,
+    ResolvedInputs,
 };
 use super::super::util::{alive, join_outputs, join_tasks, spawn};
 use super::{IntoProcess, Process};
@@ -33,6 +35,10 @@ impl IntoProcess for WorkbenchConfig {
 }
 
 impl Process for WorkbenchProcess {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) {
+        expected.add(&self.config.name, &self.config.accesses)
+    }
+
     fn run(&self, factory: &Factory) -> ChildTask<Result<(), LocalStr>> {
         let mut tasks = Vec::new();
         for Demand { i_recipe, .. } in compute_demands(factory, &self.config.recipes) {
@@ -42,6 +48,7 @@ impl Process for WorkbenchProcess {
             }
             if let Some(ResolvedInputs { mut n_sets, items, .. }) = resolve_inputs(factory, recipe) {
                 n_sets = n_sets.min(recipe.max_sets);
+                factory.report_demand(&self.config.name, i_recipe, &*recipe.outputs, n_sets);
                 let mut bus_slots = Vec::new();
                 let slots_to_free = Rc::new(RefCell::new(Vec::new()));
                 for (i_input, (item, _)) in items.into_iter().enumerate() {
@@ -74,6 +81,7 @@ impl Process for WorkbenchProcess {
                     let mut slots_to_free = Rc::into_inner(slots_to_free).unwrap().into_inner();
                     let task = async {
                         let bus_slots = bus_slots?;
+                        let journal;
                         let tasks = {
                             alive!(weak, this);
                             upgrade!(factory, factory);
@@ -81,6 +89,7 @@ impl Process for WorkbenchProcess {
                             let access = server.load_balance(&this.config.accesses);
                             let mut group = Vec::new();
                             let recipe = &this.config.recipes[i_recipe];
+                            journal = factory.journal_begin(&this.config.name, get_undo(access, recipe));
                             for (i_input, input) in recipe.inputs.iter().enumerate() {
                                 for inv_slot in &input.slots {
                                     load_input(&mut group, access, bus_slots[i_input], *inv_slot, n_sets)
@@ -100,8 +109,10 @@ impl Process for WorkbenchProcess {
                             );
                             group.into_iter().map(|x| spawn(async move { x.await.map(|_| ()) })).collect()
                         };
-                        join_tasks(tasks).await?;
+                        let result = join_tasks(tasks).await;
                         alive_mut!(factory, factory);
+                        factory.journal_end(journal, &result);
+                        result?;
                         while slots_to_free.len() > 1 {
                             factory.bus_free(slots_to_free.pop().unwrap())
                         }
@@ -115,6 +126,32 @@ impl Process for WorkbenchProcess {
         }
         spawn(async move { join_tasks(tasks).await })
     }
+
+    fn get_recipes(&self) -> Vec<PlanRecipe> { get_plan_recipes(&self.config.name, &self.config.recipes) }
+}
+
+// On failure or restart, non-consumables go back to their storage slots and the rest of the grid to storage.
+fn get_undo(access: &BusAccess, recipe: &CraftingGridRecipe) -> Vec<Undo> {
+    let mut result = Vec::new();
+    for non_consumable in &recipe.non_consumables {
+        result.push(Undo::Move {
+            client: access.client.to_std_string(),
+            addr: access.inv_addr.to_std_string(),
+            method: "pushItems".to_owned(),
+            other: access.inv_addr.to_std_string(),
+            from_slot: non_consumable.crafting_grid_slot,
+            to_slot: non_consumable.storage_slot + 10,
+        })
+    }
+    for slot in 0..10 {
+        result.push(Undo::Return {
+            client: access.client.to_std_string(),
+            bus_addr: access.bus_addr.to_std_string(),
+            inv_addr: access.inv_addr.to_std_string(),
+            slot,
+        })
+    }
+    result
 }
 
 fn load_input(group: &mut Vec<Call>, access: &BusAccess, bus_slot: usize, inv_slot: usize, size: i32) {
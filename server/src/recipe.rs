@@ -10,6 +10,28 @@ use std::{
 
 pub trait Outputs {
     fn get_priority(&self, factory: &Factory) -> Option<f64>;
+    // What one set produces, used by the planner to find recipes for intermediates.
+    fn get_products(&self) -> Vec<Product> { Vec::new() }
+}
+
+#[derive(Clone)]
+pub struct Product {
+    pub item: Filter,
+    pub n_per_set: i32,
+}
+
+struct WithProducts<T> {
+    priority: T,
+    products: Vec<Product>,
+}
+
+impl<T: Fn(&Factory) -> Option<f64>> Outputs for WithProducts<T> {
+    fn get_priority(&self, factory: &Factory) -> Option<f64> { (self.priority)(factory) }
+    fn get_products(&self) -> Vec<Product> { self.products.clone() }
+}
+
+fn with_products(priority: impl Fn(&Factory) -> Option<f64> + 'static, products: Vec<Product>) -> Rc<dyn Outputs> {
+    Rc::new(WithProducts { priority, products })
 }
 
 impl<T: Fn(&Factory) -> Option<f64>> Outputs for T {
@@ -21,19 +43,25 @@ pub trait BoxedOutputs {
     fn or(self, other: Self) -> Self;
     fn not(self) -> Self;
     fn map_priority(self, f: impl Fn(&Factory, f64) -> f64 + 'static) -> Self;
+    // Sets how many of each product one set yields (default 1).
+    fn yields(self, n_per_set: i32) -> Self;
 }
 
 impl BoxedOutputs for Rc<dyn Outputs> {
     fn and(self, other: Self) -> Self {
-        Rc::new(move |factory: &_| {
+        let products = [self.get_products(), other.get_products()].concat();
+        let priority = move |factory: &_| {
             max_by(self.get_priority(factory), other.get_priority(factory), |x, y| x.partial_cmp(y).unwrap())
-        })
+        };
+        with_products(priority, products)
     }
 
     fn or(self, other: Self) -> Self {
-        Rc::new(move |factory: &_| {
+        let products = [self.get_products(), other.get_products()].concat();
+        let priority = move |factory: &_| {
             min_by(self.get_priority(factory), other.get_priority(factory), |x, y| x.partial_cmp(y).unwrap())
-        })
+        };
+        with_products(priority, products)
     }
 
     fn not(self) -> Self {
@@ -44,7 +72,13 @@ impl BoxedOutputs for Rc<dyn Outputs> {
     }
 
     fn map_priority(self, f: impl Fn(&Factory, f64) -> f64 + 'static) -> Self {
-        Rc::new(move |factory: &_| self.get_priority(factory).map(|x| f(factory, x)))
+        let products = self.get_products();
+        with_products(move |factory: &_| self.get_priority(factory).map(|x| f(factory, x)), products)
+    }
+
+    fn yields(self, n_per_set: i32) -> Self {
+        let products = Vec::from_iter(self.get_products().into_iter().map(|x| Product { n_per_set, ..x }));
+        with_products(move |factory: &_| self.get_priority(factory), products)
     }
 }
 
@@ -69,6 +103,8 @@ impl Outputs for Output {
             None
         }
     }
+
+    fn get_products(&self) -> Vec<Product> { vec![Product { item: self.item.clone(), n_per_set: 1 }] }
 }
 
 pub struct FluidOutput {
@@ -138,6 +174,44 @@ macro_rules! impl_recipe {
     };
 }
 
+pub struct PlanInput {
+    pub item: Filter,
+    pub size: i32,
+    pub allow_backup: bool,
+    pub extra_backup: i32,
+}
+
+// Process-independent view of a recipe for the planner.
+pub struct PlanRecipe {
+    pub process: LocalStr,
+    pub products: Vec<Product>,
+    pub inputs: Vec<PlanInput>,
+}
+
+pub fn get_plan_recipes(process: &LocalStr, recipes: &[impl Recipe]) -> Vec<PlanRecipe> {
+    let mut result = Vec::new();
+    for recipe in recipes {
+        let products = recipe.get_outputs().get_products();
+        if products.is_empty() {
+            continue;
+        }
+        let inputs = Vec::from_iter(recipe.get_inputs().iter().map(|input| PlanInput {
+            item: input.get_item().clone(),
+            size: input.get_size(),
+            allow_backup: input.get_allow_backup(),
+            extra_backup: input.get_extra_backup(),
+        }));
+        result.push(PlanRecipe { process: process.clone(), products, inputs })
+    }
+    result
+}
+
+// Recipes whose outputs are satisfied still run when the planner needs one of their products.
+pub fn get_demand_priority(factory: &Factory, recipe: &impl Recipe) -> Option<f64> {
+    let outputs = recipe.get_outputs();
+    outputs.get_priority(factory).or_else(|| factory.get_plan_priority(outputs))
+}
+
 pub struct ResolvedInputs {
     pub n_sets: i32,
     pub priority: i32,
@@ -178,7 +252,10 @@ pub fn resolve_inputs(factory: &Factory, recipe: &impl Recipe) -> Option<Resolve
         let limit = input_info.n_available / input_info.n_needed;
         availability_bound = availability_bound.min(limit)
     }
-    let n_sets = max_size_bound.min(availability_bound);
+    // Recipes only run for plans are limited to the sets they still need.
+    let outputs = recipe.get_outputs();
+    let plan_bound = if outputs.get_priority(factory).is_some() { i32::MAX } else { factory.get_plan_sets(outputs) };
+    let n_sets = max_size_bound.min(availability_bound).min(plan_bound);
     if n_sets > 0 {
         Some(ResolvedInputs { n_sets, priority: availability_bound, items })
     } else {
@@ -195,7 +272,7 @@ pub struct Demand {
 pub fn compute_demands(factory: &Factory, recipes: &[impl Recipe]) -> Vec<Demand> {
     let mut result = Vec::new();
     for (i_recipe, recipe) in recipes.iter().enumerate() {
-        let Some(mut priority) = recipe.get_outputs().get_priority(factory) else { continue };
+        let Some(mut priority) = get_demand_priority(factory, recipe) else { continue };
         let Some(inputs) = resolve_inputs(factory, recipe) else { continue };
         priority *= inputs.priority as f64;
         result.push(Demand { i_recipe, inputs, priority })
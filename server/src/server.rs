@@ -1,32 +1,133 @@
-use crate::lua_value::{serialize, table_remove, vec_to_table, Parser, Table, Value};
-use crate::{access::GetClient, action::ActionRequest, util::spawn, Tui};
+use crate::action::{Action, ActionFuture, ActionRequest, Lost};
+use crate::item::Api;
+use crate::lua_value::{serialize_versioned, table_remove, vec_to_table, Key, Parser, Table, Value};
+use crate::lua_value::{PROTOCOL_COMPACT, PROTOCOL_TEXT};
+use crate::{access::GetClient, util::spawn, Tui};
 use abort_on_drop::ChildTask;
 use flexstr::{local_fmt, LocalStr};
 use fnv::FnvHashMap;
 use futures_util::{
+    future::{FutureExt, LocalBoxFuture},
     sink::SinkExt,
     stream::{SplitSink, SplitStream, StreamExt},
 };
+use hex::{FromHex, ToHex};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use socket2::{Domain, SockAddr, Socket, Type};
 use std::{
     cell::RefCell,
-    collections::VecDeque,
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
     fmt::Write,
     mem::replace,
     net::{Ipv6Addr, SocketAddr},
     rc::{Rc, Weak},
+    sync::Arc,
     time::Duration,
 };
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream},
-    time::sleep,
+    time::{sleep, sleep_until, Instant},
 };
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
 use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
 
+// Either a plain or a TLS connection.
+trait Io: AsyncRead + AsyncWrite + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Unpin> Io for T {}
+type Stream = Box<dyn Io>;
+type OnLogin = Box<dyn Fn(&LocalStr)>;
+
+pub struct ClientInfo {
+    pub addr: SocketAddr,
+    pub login: Option<LocalStr>,
+    pub load: usize,
+    pub n_queued: usize,
+    pub n_in_flight: usize,
+    pub latency: Option<Duration>,
+    pub protocol: u8,
+    pub capabilities: Option<Rc<Capabilities>>,
+}
+
+// What a client reported about itself at login (`c` in the login table).
+pub struct Capabilities {
+    // Reported if any inventory is attached.
+    pub api: Option<Api>,
+    pub turtle: bool,
+    // Supports `CallMany`.
+    pub batch: bool,
+    pub computer_id: Option<i32>,
+    // Type by name.
+    pub peripherals: FnvHashMap<LocalStr, LocalStr>,
+}
+
+fn optional<T: TryFrom<Value, Error = LocalStr>>(table: &mut Table, key: &'static str) -> Result<Option<T>, LocalStr> {
+    table.remove(&key.into()).map(T::try_from).transpose()
+}
+
+impl Capabilities {
+    fn parse(value: Value) -> Result<Self, LocalStr> {
+        let mut table = Table::try_from(value)?;
+        let mut peripherals = FnvHashMap::default();
+        for (name, kind) in optional::<Table>(&mut table, "peripherals")?.unwrap_or_default() {
+            let Key::S(name) = name else { return Err(local_fmt!("invalid peripheral name: {:?}", name)) };
+            peripherals.insert(name, kind.try_into()?);
+        }
+        Ok(Capabilities {
+            api: optional::<LocalStr>(&mut table, "api")?.map(|x| Api::parse(&x)).transpose()?,
+            turtle: optional(&mut table, "turtle")?.unwrap_or_default(),
+            batch: optional(&mut table, "batch")?.unwrap_or_default(),
+            computer_id: optional(&mut table, "id")?,
+            peripherals,
+        })
+    }
+}
+
+pub struct TimeoutConfig {
+    pub default: Duration,
+    // By `Action::get_method`.
+    pub methods: FnvHashMap<LocalStr, Duration>,
+    // How many times a timed-out request that doesn't change the world (see `Action::is_mutating`) is sent again.
+    pub n_retries: usize,
+    // Whether a timeout disconnects the client, failing all its requests, or only fails the request.
+    pub disconnect: bool,
+}
+
+impl TimeoutConfig {
+    fn get(&self, method: &str) -> Duration { self.methods.get(method).copied().unwrap_or(self.default) }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            default: Duration::from_secs(30),
+            methods: FnvHashMap::default(),
+            n_retries: 0,
+            disconnect: true,
+        }
+    }
+}
+
 pub struct Server {
+    weak: Weak<RefCell<Server>>,
     pub tui: Rc<Tui>,
     clients: Option<Rc<RefCell<Client>>>,
     logins: FnvHashMap<LocalStr, Weak<RefCell<Client>>>,
+    // When non-empty, only these logins are accepted, each after a challenge-response with its secret.
+    secrets: FnvHashMap<LocalStr, LocalStr>,
+    // Mutating actions are logged instead of sent, see `ActionRequest::intercept`.
+    dry_run: bool,
+    // Requests sent to a client and not yet answered, beyond which the rest wait in its `request_queue`.
+    max_in_flight: Option<usize>,
+    timeouts: TimeoutConfig,
+    // Peripheral flavour of clients that don't report one at login.
+    apis: FnvHashMap<LocalStr, Api>,
+    default_api: Api,
+    // Called with the name of each client that logs in, while the server is borrowed.
+    on_login: Option<OnLogin>,
     _acceptor: ChildTask<()>,
 }
 
@@ -39,34 +140,75 @@ impl Drop for Server {
 }
 
 enum WriterState {
-    NotWriting(SplitSink<WebSocketStream<TcpStream>, Message>),
+    NotWriting(SplitSink<WebSocketStream<Stream>, Message>),
     Writing { _writer: ChildTask<()> },
     Invalid,
 }
 
+struct Pending {
+    request: Rc<RefCell<dyn ActionRequest>>,
+    method: LocalStr,
+    mutating: bool,
+    sent: Instant,
+    timeout: Duration,
+    // The request to send again on timeout, and how many more times.
+    retry: Option<(Table, usize)>,
+}
+
 struct Client {
     weak: Weak<RefCell<Client>>,
     tui: Rc<Tui>,
+    addr: SocketAddr,
     log_prefix: String,
     next: Option<Rc<RefCell<Client>>>,
     prev: Option<Weak<RefCell<Client>>>,
     server: Weak<RefCell<Server>>,
     login: Option<LocalStr>,
+    // Login and nonce awaiting the HMAC response.
+    challenge: Option<(LocalStr, LocalStr)>,
+    // Encoding of packets after the login, see `lua_value::PROTOCOL_TEXT`.
+    protocol: u8,
+    capabilities: Option<Rc<Capabilities>>,
     _reader: ChildTask<()>,
+    packet_queue: VecDeque<Vec<u8>>,
     request_queue: VecDeque<Vec<Rc<RefCell<dyn ActionRequest>>>>,
     request_queue_size: usize,
+    // Timed-out requests to send again, under the same `max_in_flight` limit as the `request_queue`, which they precede.
+    retry_queue: VecDeque<(Table, Pending)>,
     next_request_id: usize,
-    response_queue: FnvHashMap<usize, Rc<RefCell<dyn ActionRequest>>>,
+    response_queue: FnvHashMap<usize, Pending>,
+    // Deadlines of `response_queue`, including ones already answered.
+    deadlines: BinaryHeap<Reverse<(Instant, usize)>>,
+    // Requests that timed out without disconnecting, whose responses are ignored, with when they timed out.
+    expired: FnvHashMap<usize, Instant>,
+    // Moving average of the response time.
+    latency: Option<Duration>,
     writer: WriterState,
-    timeout: Option<ChildTask<()>>,
+    // The earliest deadline and the task waiting for it.
+    timeout: Option<(Instant, ChildTask<()>)>,
+    login_timeout: Option<ChildTask<()>>,
 }
 
+// How long after timing out a response is still expected, and ignored when it comes.
+const MAX_RESPONSE_DELAY: Duration = Duration::from_secs(600);
+
 impl Drop for Client {
     fn drop(&mut self) {
         self.log(format_args!("disconnected"));
         let message: LocalStr = [&self.log_prefix, " disconnected"].into_iter().collect();
-        for x in self.request_queue.iter().flatten().chain(self.response_queue.values()) {
-            x.borrow_mut().on_fail(message.clone())
+        for x in self.request_queue.iter().flatten() {
+            x.borrow_mut().on_lost(message.clone(), Lost::Unsent)
+        }
+        for (_, x) in &self.retry_queue {
+            x.request.borrow_mut().on_lost(message.clone(), Lost::Unsent)
+        }
+        for x in self.response_queue.values() {
+            let reason = if x.mutating {
+                local_fmt!("{} disconnected before answering {}, outcome unknown", self.log_prefix, x.method)
+            } else {
+                message.clone()
+            };
+            x.request.borrow_mut().on_lost(reason, Lost::Unanswered)
         }
     }
 }
@@ -96,52 +238,156 @@ impl Client {
     fn enqueue_request_group(&mut self, group: Vec<Rc<RefCell<dyn ActionRequest>>>) {
         self.request_queue_size += group.len();
         self.request_queue.push_back(group);
+        self.start_writer()
+    }
+
+    // Sends a value outside of the request-response protocol, before any queued requests.
+    fn enqueue_packet(&mut self, value: Value) {
+        let mut data = Vec::new();
+        serialize_versioned(self.protocol, &value, &mut data);
+        self.packet_queue.push_back(data);
+        self.start_writer()
+    }
+
+    fn start_writer(&mut self) {
         let writer = replace(&mut self.writer, WriterState::Invalid);
         if let WriterState::NotWriting(stream) = writer {
-            self.update_timeout(false);
             self.writer = WriterState::Writing { _writer: spawn(writer_main(self.weak.clone(), stream)) }
         } else {
             self.writer = writer
         }
     }
 
-    fn update_timeout(&mut self, restart: bool) {
-        if self.request_queue_size == 0 && self.response_queue.is_empty() {
-            self.timeout = None
-        } else if restart || self.timeout.is_none() {
-            self.timeout = Some(spawn(timeout_main(self.weak.clone())))
+    fn send_request(&mut self, id: usize, pending: Pending) {
+        self.deadlines.push(Reverse((pending.sent + pending.timeout, id)));
+        self.response_queue.insert(id, pending);
+    }
+
+    fn update_timeout(&mut self) {
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if self.response_queue.contains_key(&id) {
+                if self.timeout.as_ref().is_none_or(|x| deadline < x.0) {
+                    self.timeout = Some((deadline, spawn(timeout_main(self.weak.clone(), deadline))))
+                }
+                return;
+            }
+            self.deadlines.pop();
         }
+        self.timeout = None
+    }
+
+    fn on_timeout(&mut self) {
+        self.timeout = None;
+        let now = Instant::now();
+        let disconnect = self.server.upgrade().is_none_or(|x| x.borrow().timeouts.disconnect);
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop();
+            let Some(mut pending) = self.response_queue.remove(&id) else { continue };
+            match pending.retry.take() {
+                Some((table, n_retries)) if n_retries > 0 => {
+                    self.log(format_args!("{} timed out, retrying", pending.method));
+                    self.expired.insert(id, now);
+                    self.retry_queue
+                        .push_back((table.clone(), Pending { retry: Some((table, n_retries - 1)), ..pending }))
+                }
+                _ if disconnect => {
+                    let method = pending.method.clone();
+                    self.response_queue.insert(id, pending);
+                    return self.log_and_disconnect(format_args!("request timeout: {}", method));
+                }
+                _ => {
+                    self.expired.insert(id, now);
+                    let reason = local_fmt!("{}: {} timed out", self.log_prefix, pending.method);
+                    self.log(format_args!("{} timed out", pending.method));
+                    pending.request.borrow_mut().on_fail(reason)
+                }
+            }
+        }
+        // Responses that never come would otherwise pile up for as long as the client stays connected.
+        self.expired.retain(|_, x| now.duration_since(*x) < MAX_RESPONSE_DELAY);
+        self.update_timeout();
+        self.start_writer()
+    }
+
+    fn estimate_load(&self) -> usize { self.request_queue_size + self.retry_queue.len() + self.response_queue.len() }
+
+    // A group is only held back while others are in flight, so that groups larger than the limit still go through.
+    fn can_send(&self, group_size: usize) -> bool {
+        let max = self.server.upgrade().and_then(|x| x.borrow().max_in_flight);
+        self.response_queue.is_empty() || max.is_none_or(|max| self.response_queue.len() + group_size <= max)
+    }
+
+    fn has_spare_capacity(&self, server: &Server) -> bool {
+        server.max_in_flight.is_none_or(|max| self.response_queue.len() < max)
+    }
+
+    fn update_latency(&mut self, sample: Duration) {
+        self.latency = Some(self.latency.map_or(sample, |x| (x * 7 + sample) / 8))
     }
+}
 
-    fn estimate_load(&self) -> usize { self.request_queue_size + self.response_queue.len() }
+async fn timeout_main(client: Weak<RefCell<Client>>, deadline: Instant) {
+    sleep_until(deadline).await;
+    if let Some(this) = client.upgrade() {
+        this.borrow_mut().on_timeout()
+    }
 }
 
-async fn timeout_main(client: Weak<RefCell<Client>>) {
+async fn login_timeout_main(client: Weak<RefCell<Client>>) {
     sleep(Duration::from_secs(30)).await;
     if let Some(this) = client.upgrade() {
-        this.borrow_mut().log_and_disconnect(format_args!("request timeout"))
+        this.borrow_mut().log_and_disconnect(format_args!("login timeout"))
     }
 }
 
-async fn writer_main(client: Weak<RefCell<Client>>, mut sink: SplitSink<WebSocketStream<TcpStream>, Message>) {
+async fn writer_main(client: Weak<RefCell<Client>>, mut sink: SplitSink<WebSocketStream<Stream>, Message>) {
     loop {
         let mut data = Vec::new();
         {
             let Some(this) = client.upgrade() else { break };
             let mut this = this.borrow_mut();
-            let Some(group) = this.request_queue.pop_front() else { break this.writer = WriterState::NotWriting(sink) };
-            this.request_queue_size -= group.len();
-            let mut value = Vec::new();
-            for request in group {
+            if let Some(packet) = this.packet_queue.pop_front() {
+                data = packet
+            } else if !this.retry_queue.is_empty() && this.can_send(1) {
+                let (mut table, pending) = this.retry_queue.pop_front().unwrap();
                 let id = this.next_request_id;
                 this.next_request_id += 1;
-                let mut table = Table::new();
                 table.insert("i".into(), id.into());
-                request.borrow_mut().build_request(&mut table);
-                value.push(table.into());
-                this.response_queue.insert(id, request);
+                serialize_versioned(this.protocol, &vec_to_table(vec![table.into()]).into(), &mut data);
+                this.send_request(id, Pending { sent: Instant::now(), ..pending });
+                this.update_timeout()
+            } else if !this.retry_queue.is_empty() {
+                break this.writer = WriterState::NotWriting(sink);
+            } else {
+                let group_size = this.request_queue.front().map(|x| x.len());
+                if !group_size.is_some_and(|x| this.can_send(x)) {
+                    break this.writer = WriterState::NotWriting(sink);
+                }
+                let group = this.request_queue.pop_front().unwrap();
+                this.request_queue_size -= group.len();
+                let server = this.server.upgrade().unwrap();
+                let timeouts = &server.borrow().timeouts;
+                let now = Instant::now();
+                let mut value = Vec::new();
+                for request in group {
+                    let id = this.next_request_id;
+                    this.next_request_id += 1;
+                    let mut table = Table::new();
+                    table.insert("i".into(), id.into());
+                    let method = request.borrow().get_method();
+                    let mutating = request.borrow().is_mutating();
+                    request.borrow_mut().build_request(&mut table);
+                    let retry = (timeouts.n_retries > 0 && !mutating).then(|| (table.clone(), timeouts.n_retries));
+                    value.push(table.into());
+                    let timeout = timeouts.get(&method);
+                    this.send_request(id, Pending { request, method, mutating, sent: now, timeout, retry });
+                }
+                this.update_timeout();
+                serialize_versioned(this.protocol, &vec_to_table(value).into(), &mut data);
             }
-            serialize(&vec_to_table(value).into(), &mut data);
             #[cfg(feature = "dump_traffic")]
             this.log(format_args!("out: {}", data.iter().map(|x| char::from(*x)).collect::<String>()));
         }
@@ -166,29 +412,91 @@ fn on_packet(client: &Rc<RefCell<Client>>, value: Value) -> Result<(), LocalStr>
         };
         if !table.is_empty() {
             Err(local_fmt!("garbage in packet: {:?}", table))
-        } else if let Some(request) = this.response_queue.remove(&id) {
-            this.update_timeout(true);
+        } else if let Some(Pending { request, sent, .. }) = this.response_queue.remove(&id) {
+            this.update_latency(sent.elapsed());
+            if !this.request_queue.is_empty() || !this.retry_queue.is_empty() {
+                this.start_writer()
+            }
             match response {
                 Ok(x) => request.borrow_mut().on_response(x),
                 Err(e) => Ok(request.borrow_mut().on_fail(e)),
             }
+        } else if this.expired.remove(&id).is_some() {
+            Ok(())
         } else {
             Err(local_fmt!("unexpected response: {:?}", response))
         }
-    } else if let Value::S(login) = value {
+    } else {
+        let login = if let Some((login, nonce)) = this.challenge.take() {
+            let Value::S(response) = value else { return Err(local_fmt!("invalid challenge response: {:?}", value)) };
+            upgrade!(this.server, server);
+            if !check_login(&server.secrets, &login, &nonce, &response) {
+                return Err(local_fmt!("login rejected for {}: wrong response", login));
+            }
+            login
+        } else {
+            // Either a bare name, or `{ n = name, v = highest protocol supported, c = capabilities }`,
+            // which is answered with `{ v = protocol }` in the text encoding before switching.
+            let (login, protocol) = match value {
+                Value::S(login) => (login, None),
+                Value::T(mut table) => {
+                    let login: LocalStr = table_remove(&mut table, "n")?;
+                    let protocol: u8 = table_remove(&mut table, "v")?;
+                    if let Some(capabilities) = table.remove(&"c".into()) {
+                        this.capabilities = Some(Rc::new(Capabilities::parse(capabilities)?))
+                    }
+                    (login, Some(protocol.clamp(PROTOCOL_TEXT, PROTOCOL_COMPACT)))
+                }
+                _ => return Err(local_fmt!("invalid login packet: {:?}", value)),
+            };
+            if let Some(protocol) = protocol {
+                this.enqueue_packet(Value::T(Table::from([("v".into(), protocol.into())])));
+                this.protocol = protocol
+            }
+            upgrade!(this.server, server);
+            if !server.secrets.is_empty() {
+                if !server.secrets.contains_key(&login) {
+                    return Err(local_fmt!("login rejected for {}: no secret configured", login));
+                }
+                let nonce = make_nonce();
+                this.challenge = Some((login, nonce.clone()));
+                this.enqueue_packet(Value::S(nonce));
+                this.login_timeout = Some(spawn(login_timeout_main(this.weak.clone())));
+                return Ok(());
+            }
+            login
+        };
         upgrade_mut!(this.server, server);
         write!(this.log_prefix, "[{}]", login).unwrap();
         this.log(format_args!("logged in"));
         this.login = Some(login.clone());
+        this.login_timeout = None;
         drop(this);
         server.login(login, Rc::downgrade(client));
         Ok(())
-    } else {
-        Err(local_fmt!("invalid login packet: {:?}", value))
     }
 }
 
-async fn reader_main(client: Weak<RefCell<Client>>, mut stream: SplitStream<WebSocketStream<TcpStream>>) {
+fn make_nonce() -> LocalStr {
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce).unwrap();
+    nonce.encode_hex()
+}
+
+// The response is the hex-encoded HMAC-SHA256 of the nonce, keyed with the client's secret.
+fn verify_response(secret: &str, nonce: &str, response: &str) -> bool {
+    let Ok(response) = <[u8; 32]>::from_hex(response) else { return false };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(nonce.as_bytes());
+    mac.verify_slice(&response).is_ok()
+}
+
+// Logins without a secret are rejected outright.
+fn check_login(secrets: &FnvHashMap<LocalStr, LocalStr>, login: &str, nonce: &str, response: &str) -> bool {
+    secrets.get(login).is_some_and(|x| verify_response(x, nonce, response))
+}
+
+async fn reader_main(client: Weak<RefCell<Client>>, mut stream: SplitStream<WebSocketStream<Stream>>) {
     let mut parser = Parser::new();
     loop {
         let data = stream.next().await;
@@ -201,6 +509,7 @@ async fn reader_main(client: Weak<RefCell<Client>>, mut stream: SplitStream<WebS
                 if let Err(e) = parser.shift(&data, &mut |x| on_packet(&this, x)) {
                     break this.borrow_mut().log_and_disconnect(format_args!("error decoding packet: {}", e));
                 }
+                parser.set_protocol(this.borrow().protocol);
             }
             Some(Ok(data)) => break this.borrow_mut().log_and_disconnect(format_args!("non-binary data: {}", data)),
             None => break this.borrow_mut().log_and_disconnect(format_args!("client disconnected")),
@@ -208,8 +517,15 @@ async fn reader_main(client: Weak<RefCell<Client>>, mut stream: SplitStream<WebS
     }
 }
 
-async fn handshake_main(client: Weak<RefCell<Client>>, stream: TcpStream) {
-    let result = accept_async(stream).await;
+async fn handshake_main(client: Weak<RefCell<Client>>, stream: TcpStream, tls: Option<TlsAcceptor>) {
+    let result = async {
+        let stream: Stream = match tls {
+            Some(tls) => Box::new(tls.accept(stream).await.map_err(|e| local_fmt!("TLS: {}", e))?),
+            None => Box::new(stream),
+        };
+        accept_async(stream).await.map_err(|e| local_fmt!("{}", e))
+    }
+    .await;
     if let Some(this) = client.upgrade() {
         let mut this = this.borrow_mut();
         match result {
@@ -224,7 +540,40 @@ async fn handshake_main(client: Weak<RefCell<Client>>, stream: TcpStream) {
     }
 }
 
-fn create_listener(port: u16) -> TcpListener {
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::N => "nil".to_owned(),
+        Value::F(x) => x.to_string(),
+        Value::S(x) => format!("{:?}", &**x),
+        Value::B(x) => x.to_string(),
+        Value::T(_) => "{...}".to_owned(),
+    }
+}
+
+// e.g. `minecraft:chest_0.pushItems("minecraft:furnace_0", 3, 8, 1)`.
+fn describe_request(request: &Table) -> String {
+    let get = |key: &'static str| match request.get(&key.into()) {
+        Some(Value::S(x)) => x.to_string(),
+        x => x.map_or_else(|| "nil".to_owned(), describe_value),
+    };
+    let args = match request.get(&"v".into()) {
+        Some(Value::T(args)) => Vec::from_iter(args.values().map(describe_value)),
+        _ => Vec::new(),
+    };
+    match &*get("o") {
+        "c" => format!(
+            "{}.{}({})",
+            get("p"),
+            args.first().map_or("", |x| x.trim_matches('"')),
+            args.get(1..).unwrap_or(&[]).join(", ")
+        ),
+        "t" => format!("turtle.{}({})", get("f"), args.join(", ")),
+        "o" => format!("redstone {}:{} = {} (bits {})", get("p"), get("s"), get("v"), get("b")),
+        _ => format!("{:?}", request),
+    }
+}
+
+pub fn create_listener(port: u16) -> TcpListener {
     let socket = Socket::new(Domain::IPV6, Type::STREAM, None).unwrap();
     socket.set_reuse_address(true).unwrap();
     socket.set_only_v6(false).unwrap();
@@ -234,7 +583,20 @@ fn create_listener(port: u16) -> TcpListener {
     TcpListener::from_std(socket.into()).unwrap()
 }
 
-async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener) {
+// Loads a PEM certificate chain and private key for serving `wss://`.
+pub fn load_tls(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, LocalStr> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| local_fmt!("{}: {}", cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| local_fmt!("{}: {}", key_path, e))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| local_fmt!("invalid certificate: {}", e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener, tls: Option<TlsAcceptor>) {
     loop {
         let (stream, addr) = listener.accept().await.unwrap();
         let Some(this) = server.upgrade() else { break };
@@ -243,18 +605,28 @@ async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener) {
             let client = Client {
                 weak: weak.clone(),
                 tui: this.tui.clone(),
+                addr,
                 log_prefix: addr.to_string(),
                 next: this.clients.take(),
                 prev: None,
                 server: server.clone(),
                 login: None,
-                _reader: spawn(handshake_main(weak.clone(), stream)),
+                challenge: None,
+                protocol: PROTOCOL_TEXT,
+                capabilities: None,
+                _reader: spawn(handshake_main(weak.clone(), stream, tls.clone())),
+                packet_queue: VecDeque::new(),
                 request_queue: VecDeque::new(),
                 request_queue_size: 0,
+                retry_queue: VecDeque::new(),
                 next_request_id: 0,
                 response_queue: FnvHashMap::default(),
+                deadlines: BinaryHeap::new(),
+                expired: FnvHashMap::default(),
+                latency: None,
                 writer: WriterState::Invalid,
                 timeout: None,
+                login_timeout: None,
             };
             if let Some(ref next) = client.next {
                 next.borrow_mut().prev = Some(weak.clone())
@@ -266,18 +638,42 @@ async fn acceptor_main(server: Weak<RefCell<Server>>, listener: TcpListener) {
 }
 
 impl Server {
-    pub fn new(tui: Rc<Tui>, port: u16) -> Rc<RefCell<Self>> {
+    pub fn new(tui: Rc<Tui>, port: u16, tls: Option<TlsAcceptor>) -> Rc<RefCell<Self>> {
         Rc::new_cyclic(|weak| {
             RefCell::new(Server {
+                weak: weak.clone(),
                 tui,
                 clients: None,
                 logins: FnvHashMap::default(),
-                _acceptor: spawn(acceptor_main(weak.clone(), create_listener(port))),
+                secrets: FnvHashMap::default(),
+                dry_run: false,
+                max_in_flight: None,
+                timeouts: TimeoutConfig::default(),
+                apis: FnvHashMap::default(),
+                default_api: Api::default(),
+                on_login: None,
+                _acceptor: spawn(acceptor_main(weak.clone(), create_listener(port), tls)),
             })
         })
     }
 
+    pub fn set_secrets(&mut self, secrets: FnvHashMap<LocalStr, LocalStr>) { self.secrets = secrets }
+    pub fn set_dry_run(&mut self, dry_run: bool) { self.dry_run = dry_run }
+    pub fn is_dry_run(&self) -> bool { self.dry_run }
+    pub fn set_max_in_flight(&mut self, max_in_flight: Option<usize>) { self.max_in_flight = max_in_flight }
+    pub fn set_timeouts(&mut self, timeouts: TimeoutConfig) { self.timeouts = timeouts }
+    pub fn set_apis(&mut self, default: Api, apis: FnvHashMap<LocalStr, Api>) {
+        self.default_api = default;
+        self.apis = apis
+    }
+
+    pub fn set_on_login(&mut self, on_login: OnLogin) { self.on_login = Some(on_login) }
+    pub fn get_logins(&self) -> Vec<LocalStr> { Vec::from_iter(self.logins.keys().cloned()) }
+
     fn login(&mut self, name: LocalStr, client: Weak<RefCell<Client>>) {
+        if let Some(on_login) = &self.on_login {
+            on_login(&name)
+        }
         if let Some(old) = self.logins.insert(name, client) {
             upgrade_mut!(old, old);
             old.log(format_args!("logged in from another address"));
@@ -286,22 +682,66 @@ impl Server {
         }
     }
 
-    pub fn enqueue_request_group(&self, client: &str, group: Vec<Rc<RefCell<dyn ActionRequest>>>) {
+    pub fn enqueue_request_group(&self, client: &str, mut group: Vec<Rc<RefCell<dyn ActionRequest>>>) {
+        if self.dry_run {
+            group.retain(|x| {
+                let Some(request) = x.borrow_mut().intercept() else { return true };
+                self.tui.log(format!("dry-run {}: {}", client, describe_request(&request)), 10);
+                false
+            });
+            if group.is_empty() {
+                return;
+            }
+        }
         if let Some(client) = self.logins.get(client) {
             client.upgrade().unwrap().borrow_mut().enqueue_request_group(group)
         } else {
             let reason = local_fmt!("{} isn't connected", client);
             for x in group {
-                x.borrow_mut().on_fail(reason.clone())
+                x.borrow_mut().on_lost(reason.clone(), Lost::Unsent)
             }
         }
     }
 
-    fn estimate_load(&self, client: &str) -> usize {
+    pub fn get_clients(&self) -> Vec<ClientInfo> {
+        let mut result = Vec::new();
+        let mut next = self.clients.clone();
+        while let Some(client) = next {
+            let client = client.borrow();
+            result.push(ClientInfo {
+                addr: client.addr,
+                login: client.login.clone(),
+                load: client.estimate_load(),
+                n_queued: client.request_queue_size + client.retry_queue.len(),
+                n_in_flight: client.response_queue.len(),
+                latency: client.latency,
+                protocol: client.protocol,
+                capabilities: client.capabilities.clone(),
+            });
+            next = client.next.clone()
+        }
+        result
+    }
+
+    // `None` if the client isn't logged in or didn't report its capabilities.
+    pub fn get_capabilities(&self, client: &str) -> Option<Rc<Capabilities>> {
+        self.logins.get(client)?.upgrade()?.borrow().capabilities.clone()
+    }
+
+    // What the client reported at login, otherwise what's configured for it.
+    pub fn get_api(&self, client: &str) -> Api {
+        let reported = self.get_capabilities(client).and_then(|x| x.api);
+        reported.or_else(|| self.apis.get(client).copied()).unwrap_or(self.default_api)
+    }
+
+    // Clients with spare capacity come first, then the least loaded.
+    fn estimate_load(&self, client: &str) -> (bool, usize) {
         if let Some(client) = self.logins.get(client) {
-            client.upgrade().unwrap().borrow().estimate_load()
+            let client = client.upgrade().unwrap();
+            let client = client.borrow();
+            (!client.has_spare_capacity(self), client.estimate_load())
         } else {
-            usize::MAX
+            (true, usize::MAX)
         }
     }
 
@@ -318,4 +758,104 @@ impl Server {
         }
         best_access
     }
+
+    // Sends `make(access)` through the least loaded of `accesses`. If the client disconnects, the action is sent again
+    // through the next one, unless it was sent and is mutating; the error then says that its outcome is unknown.
+    pub fn call_with_failover<'a, T: GetClient + 'a, A: Action>(
+        &self,
+        accesses: impl IntoIterator<Item = &'a T>,
+        make: impl Fn(&'a T) -> A,
+    ) -> LocalBoxFuture<'static, Result<A::Output, LocalStr>> {
+        self.call_with_failover_from(accesses, make).map(|x| x.map(|(_, output)| output)).boxed_local()
+    }
+
+    // Same as `call_with_failover`, also returning the client that answered.
+    pub fn call_with_failover_from<'a, T: GetClient + 'a, A: Action>(
+        &self,
+        accesses: impl IntoIterator<Item = &'a T>,
+        make: impl Fn(&'a T) -> A,
+    ) -> LocalBoxFuture<'static, Result<(LocalStr, A::Output), LocalStr>> {
+        let mut accesses = Vec::from_iter(accesses);
+        accesses.sort_by_cached_key(|x| self.estimate_load(x.get_client()));
+        let mut actions = VecDeque::from_iter(accesses.into_iter().map(|x| (LocalStr::from(x.get_client()), make(x))));
+        let (mut client, action) = actions.pop_front().unwrap();
+        let mut mutating = action.is_mutating();
+        let mut action = ActionFuture::from(action);
+        self.enqueue_request_group(&client, vec![action.clone().into()]);
+        let server = self.weak.clone();
+        async move {
+            loop {
+                let (e, lost) = match (action.clone().await, action.get_lost()) {
+                    (Err(e), Some(lost)) => (e, lost),
+                    (result, _) => return result.map(|x| (client, x)),
+                };
+                if mutating && lost == Lost::Unanswered {
+                    return Err(e);
+                }
+                let Some((next_client, next)) = actions.pop_front() else { return Err(e) };
+                let Some(server) = server.upgrade() else { return Err(e) };
+                let server = server.borrow();
+                server.tui.log(format!("{}: {} failed over to {}", client, next.get_method(), next_client), 0);
+                (client, mutating, action) = (next_client, next.is_mutating(), ActionFuture::from(next));
+                server.enqueue_request_group(&client, vec![action.clone().into()])
+            }
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn respond(secret: &str, nonce: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(nonce.as_bytes());
+        mac.finalize().into_bytes().encode_hex()
+    }
+
+    fn secrets() -> FnvHashMap<LocalStr, LocalStr> {
+        FnvHashMap::from_iter([("1a".into(), "hunter2".into()), ("1b".into(), "correct horse".into())])
+    }
+
+    #[test]
+    fn accepts_right_response() {
+        let nonce = make_nonce();
+        assert!(check_login(&secrets(), "1a", &nonce, &respond("hunter2", &nonce)));
+        assert!(check_login(&secrets(), "1b", &nonce, &respond("correct horse", &nonce).to_uppercase()))
+    }
+
+    #[test]
+    fn rejects_wrong_secret() {
+        let nonce = make_nonce();
+        assert!(!check_login(&secrets(), "1a", &nonce, &respond("hunter3", &nonce)));
+        // Another client's secret.
+        assert!(!check_login(&secrets(), "1a", &nonce, &respond("correct horse", &nonce)));
+        assert!(!check_login(&secrets(), "1a", &nonce, &respond("", &nonce)))
+    }
+
+    #[test]
+    fn rejects_missing_secret() {
+        let nonce = make_nonce();
+        assert!(!check_login(&secrets(), "2a", &nonce, &respond("hunter2", &nonce)));
+        assert!(!check_login(&secrets(), "2a", &nonce, &respond("", &nonce)));
+        assert!(!check_login(&FnvHashMap::default(), "1a", &nonce, &respond("hunter2", &nonce)))
+    }
+
+    #[test]
+    fn rejects_replayed_nonce() {
+        let (old, new) = (make_nonce(), make_nonce());
+        assert_ne!(old, new);
+        assert!(!check_login(&secrets(), "1a", &new, &respond("hunter2", &old)))
+    }
+
+    #[test]
+    fn rejects_malformed_response() {
+        let nonce = make_nonce();
+        let response = respond("hunter2", &nonce);
+        for x in ["", "00", &response[..62], &format!("{response}00"), &response.replace(|_| true, "g")] {
+            assert!(!check_login(&secrets(), "1a", &nonce, x), "{}", x)
+        }
+        assert!(!check_login(&secrets(), "1a", &nonce, &"0".repeat(64)))
+    }
 }
@@ -0,0 +1,849 @@
+use crate::item::Api;
+use crate::lua_value::{
+    serialize, serialize_versioned, table_remove, table_to_vec, vec_to_table, Parser, Table, Value,
+};
+use crate::lua_value::{PROTOCOL_COMPACT, PROTOCOL_TEXT};
+use crate::{util::spawn, Tui};
+use abort_on_drop::ChildTask;
+use flexstr::{local_fmt, local_str, LocalStr};
+use fnv::FnvHashMap;
+use futures_util::{SinkExt, StreamExt};
+use hex::ToHex;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{cell::RefCell, fs::read_to_string, mem::take, rc::Rc, time::Duration};
+use tokio::time::{sleep, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+// Offline stand-in for the ComputerCraft side: clients that log in to the server like `client.lua` does,
+// backed by a simulated world of inventories, tanks, machines and redstone loaded from a JSON file.
+
+fn default_url() -> String { "ws://127.0.0.1:1847/".to_owned() }
+fn default_max_size() -> i32 { 64 }
+fn default_n_tanks() -> usize { 1 }
+fn default_protocol() -> u8 { PROTOCOL_COMPACT }
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientFile {
+    name: String,
+    #[serde(default)]
+    secret: Option<String>,
+    // Highest protocol offered at login; 1 logs in with a bare name like older clients.
+    #[serde(default = "default_protocol")]
+    protocol: u8,
+    // Answers like Plethora's peripherals instead of generic ones.
+    #[serde(default)]
+    plethora: bool,
+}
+
+impl ClientFile {
+    fn api(&self) -> Api {
+        if self.plethora {
+            Api::Plethora
+        } else {
+            Api::Generic
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemFile {
+    name: String,
+    label: String,
+    #[serde(default = "default_max_size")]
+    max_size: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StackFile {
+    // 0-based; the first empty slot if omitted.
+    #[serde(default)]
+    slot: Option<usize>,
+    name: String,
+    #[serde(default)]
+    nbt: Option<String>,
+    count: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MachineRecipeFile {
+    inputs: Vec<(String, i32)>,
+    outputs: Vec<(String, i32)>,
+    // In seconds.
+    #[serde(default)]
+    time: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MachineFile {
+    input_slots: Vec<usize>,
+    output_slots: Vec<usize>,
+    recipes: Vec<MachineRecipeFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InventoryFile {
+    addr: String,
+    size: usize,
+    #[serde(default)]
+    contents: Vec<StackFile>,
+    #[serde(default)]
+    machine: Option<MachineFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TankFile {
+    addr: String,
+    #[serde(default = "default_n_tanks")]
+    n_tanks: usize,
+    capacity: i64,
+    #[serde(default)]
+    contents: Vec<(String, i64)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedstoneFile {
+    // A redstone integrator, or the computer itself if omitted.
+    #[serde(default)]
+    addr: Option<String>,
+    side: String,
+    // Analog level, or bundled colors as a bit mask.
+    value: i32,
+}
+
+// An ME or RS bridge, holding any amount of items without NBT.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BridgeFile {
+    addr: String,
+    #[serde(default)]
+    contents: Vec<(String, i64)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorldFile {
+    #[serde(default = "default_url")]
+    url: String,
+    clients: Vec<ClientFile>,
+    #[serde(default)]
+    items: Vec<ItemFile>,
+    #[serde(default)]
+    inventories: Vec<InventoryFile>,
+    #[serde(default)]
+    tanks: Vec<TankFile>,
+    #[serde(default)]
+    redstone: Vec<RedstoneFile>,
+    #[serde(default)]
+    bridges: Vec<BridgeFile>,
+}
+
+#[derive(Clone)]
+struct Stack {
+    name: LocalStr,
+    nbt: Option<LocalStr>,
+    count: i32,
+}
+
+impl Stack {
+    fn is_same_item(&self, other: &Stack) -> bool { self.name == other.name && self.nbt == other.nbt }
+}
+
+struct MachineRecipe {
+    inputs: Vec<(LocalStr, i32)>,
+    outputs: Vec<(LocalStr, i32)>,
+    time: Duration,
+}
+
+struct Machine {
+    input_slots: Vec<usize>,
+    output_slots: Vec<usize>,
+    recipes: Vec<MachineRecipe>,
+    // Outputs of the running recipe and when they are ready.
+    busy: Option<(Instant, Vec<(LocalStr, i32)>)>,
+}
+
+struct Inventory {
+    slots: Vec<Option<Stack>>,
+    machine: Option<Machine>,
+}
+
+struct Tank {
+    capacity: i64,
+    tanks: Vec<Option<(LocalStr, i64)>>,
+}
+
+struct World {
+    // Label and max stack size by name. Unknown items are labeled with their name and stack to 64.
+    items: FnvHashMap<LocalStr, (LocalStr, i32)>,
+    inventories: FnvHashMap<LocalStr, Inventory>,
+    tanks: FnvHashMap<LocalStr, Tank>,
+    redstone: FnvHashMap<(Option<LocalStr>, LocalStr), i32>,
+    bridges: FnvHashMap<LocalStr, FnvHashMap<LocalStr, i64>>,
+}
+
+fn arg<T: TryFrom<Value, Error = LocalStr>>(args: &mut impl Iterator<Item = Value>) -> Result<Option<T>, LocalStr> {
+    match args.next() {
+        None | Some(Value::N) => Ok(None),
+        Some(x) => T::try_from(x).map(Some),
+    }
+}
+
+fn required<T>(x: Option<T>) -> Result<T, LocalStr> { x.ok_or_else(|| local_str!("missing argument")) }
+
+// Inserts into `allowed` slots, topping up matching stacks before using empty ones. Returns the number inserted.
+fn insert(slots: &mut [Option<Stack>], allowed: &[usize], stack: &Stack, max_size: i32) -> i32 {
+    let mut remaining = stack.count;
+    for &slot in allowed {
+        if let Some(x) = &mut slots[slot] {
+            if x.is_same_item(stack) {
+                let n = remaining.min(max_size - x.count).max(0);
+                x.count += n;
+                remaining -= n
+            }
+        }
+    }
+    for &slot in allowed {
+        if remaining > 0 && slots[slot].is_none() {
+            let n = remaining.min(max_size);
+            slots[slot] = Some(Stack { count: n, ..stack.clone() });
+            remaining -= n
+        }
+    }
+    stack.count - remaining
+}
+
+fn remove(slots: &mut [Option<Stack>], slot: usize, n: i32) {
+    if let Some(stack) = &mut slots[slot] {
+        stack.count -= n;
+        if stack.count <= 0 {
+            slots[slot] = None
+        }
+    }
+}
+
+impl World {
+    // Every computer sees every peripheral, typed by its address without the `_<n>` suffix,
+    // followed by generic peripheral types.
+    fn get_peripherals(&self) -> FnvHashMap<LocalStr, Vec<LocalStr>> {
+        let mut result = FnvHashMap::<_, Vec<_>>::default();
+        let inventories = self.inventories.keys().map(|x| (x, "inventory"));
+        let tanks = self.tanks.keys().map(|x| (x, "fluid_storage"));
+        let redstone = self.redstone.keys().filter_map(|(addr, _)| addr.as_ref()).map(|x| (x, "redstoneIntegrator"));
+        let bridges = self.bridges.keys().map(|x| (x, "meBridge"));
+        for (addr, kind) in inventories.chain(tanks).chain(redstone).chain(bridges) {
+            let types = result.entry(addr.clone()).or_default();
+            if types.is_empty() && kind != "redstoneIntegrator" {
+                let own = addr.rsplit_once('_').filter(|(_, n)| n.parse::<usize>().is_ok()).map_or(&**addr, |(x, _)| x);
+                types.push(own.into())
+            }
+            if !types.iter().any(|x| x == kind) {
+                types.push(kind.into())
+            }
+        }
+        result
+    }
+
+    fn get_capabilities(&self, id: usize, api: Api) -> Table {
+        let mut peripherals = Table::new();
+        for (addr, mut types) in self.get_peripherals() {
+            peripherals.insert(addr.into(), Value::S(types.swap_remove(0)));
+        }
+        Table::from([
+            ("api".into(), api.name().into()),
+            ("turtle".into(), false.into()),
+            ("batch".into(), true.into()),
+            ("id".into(), id.into()),
+            ("peripherals".into(), peripherals.into()),
+        ])
+    }
+
+    fn load(file: WorldFile) -> Result<Self, LocalStr> {
+        let mut result = World {
+            items: FnvHashMap::default(),
+            inventories: FnvHashMap::default(),
+            tanks: FnvHashMap::default(),
+            redstone: FnvHashMap::default(),
+            bridges: FnvHashMap::default(),
+        };
+        for x in file.items {
+            result.items.insert(x.name.into(), (x.label.into(), x.max_size));
+        }
+        for x in file.inventories {
+            let mut slots = Vec::new();
+            slots.resize_with(x.size, || None);
+            for stack in x.contents {
+                let allowed = Vec::from_iter(stack.slot.map_or(0..x.size, |slot| slot..slot + 1));
+                let stack = Stack { name: stack.name.into(), nbt: stack.nbt.map(LocalStr::from), count: stack.count };
+                let max_size = result.get_max_size(&stack.name);
+                if allowed.iter().any(|&slot| slot >= x.size)
+                    || insert(&mut slots, &allowed, &stack, max_size) < stack.count
+                {
+                    return Err(local_fmt!("{}: contents don't fit", x.addr));
+                }
+            }
+            if let Some(machine) = &x.machine {
+                if machine.input_slots.iter().chain(&machine.output_slots).any(|&slot| slot >= x.size) {
+                    return Err(local_fmt!("{}: machine slot out of range", x.addr));
+                }
+            }
+            let machine = x.machine.map(|x| -> Result<_, LocalStr> {
+                let recipes = x.recipes.into_iter().map(|x| {
+                    Ok(MachineRecipe {
+                        inputs: Vec::from_iter(x.inputs.into_iter().map(|(name, n)| (name.into(), n))),
+                        outputs: Vec::from_iter(x.outputs.into_iter().map(|(name, n)| (name.into(), n))),
+                        time: Duration::try_from_secs_f64(x.time)
+                            .map_err(|_| local_fmt!("invalid time: {}", x.time))?,
+                    })
+                });
+                Ok(Machine {
+                    input_slots: x.input_slots,
+                    output_slots: x.output_slots,
+                    recipes: recipes.collect::<Result<_, LocalStr>>()?,
+                    busy: None,
+                })
+            });
+            let machine = machine.transpose()?;
+            result.inventories.insert(x.addr.into(), Inventory { slots, machine });
+        }
+        for x in file.tanks {
+            let mut tanks = Vec::from_iter(x.contents.into_iter().map(|(fluid, qty)| Some((fluid.into(), qty))));
+            tanks.resize_with(x.n_tanks.max(tanks.len()), || None);
+            result.tanks.insert(x.addr.into(), Tank { capacity: x.capacity, tanks });
+        }
+        for x in file.redstone {
+            result.redstone.insert((x.addr.map(LocalStr::from), x.side.into()), x.value);
+        }
+        for x in file.bridges {
+            let contents = x.contents.into_iter().map(|(name, n)| (name.into(), n));
+            result.bridges.insert(x.addr.into(), FnvHashMap::from_iter(contents));
+        }
+        Ok(result)
+    }
+
+    fn get_max_size(&self, name: &str) -> i32 { self.items.get(name).map_or(64, |x| x.1) }
+
+    fn get_inventory(&mut self, addr: &str) -> Result<&mut Inventory, LocalStr> {
+        self.inventories.get_mut(addr).ok_or_else(|| local_fmt!("{}: no inventory", addr))
+    }
+
+    fn get_tank(&mut self, addr: &str) -> Result<&mut Tank, LocalStr> {
+        self.tanks.get_mut(addr).ok_or_else(|| local_fmt!("{}: no tank", addr))
+    }
+
+    fn get_bridge(&mut self, addr: &str) -> Result<&mut FnvHashMap<LocalStr, i64>, LocalStr> {
+        self.bridges.get_mut(addr).ok_or_else(|| local_fmt!("{}: no bridge", addr))
+    }
+
+    // Like Advanced Peripherals' `importItem` and `exportItem`, with `fromSlot` and `toSlot` in the filter.
+    fn bridge_move(&mut self, addr: &str, mut filter: Table, other: &str, import: bool) -> Result<i64, LocalStr> {
+        let name: LocalStr = table_remove(&mut filter, "name")?;
+        let mut limit = filter.remove(&"count".into()).map(i32::try_from).transpose()?.unwrap_or(i32::MAX);
+        let slot_key = if import { "fromSlot" } else { "toSlot" };
+        let slot = filter.remove(&slot_key.into()).map(usize::try_from).transpose()?.map(|x| x.wrapping_sub(1));
+        let max_size = self.get_max_size(&name);
+        let stored = *self.get_bridge(addr)?.get(&name).unwrap_or(&0);
+        let inventory = self.get_inventory(other)?;
+        let allowed = Vec::from_iter(slot.map_or(0..inventory.slots.len(), |slot| slot..slot + 1));
+        if allowed.iter().any(|&slot| slot >= inventory.slots.len()) {
+            return Err(local_fmt!("{}: slot out of range", other));
+        }
+        let n = if import {
+            let mut n = 0;
+            for slot in allowed {
+                if let Some(stack) = &inventory.slots[slot] {
+                    if stack.name == name && stack.nbt.is_none() {
+                        let moved = stack.count.min(limit);
+                        remove(&mut inventory.slots, slot, moved);
+                        (n, limit) = (n + moved, limit - moved)
+                    }
+                }
+            }
+            n
+        } else {
+            let stack = Stack { name: name.clone(), nbt: None, count: limit.min(stored.min(i32::MAX.into()) as i32) };
+            insert(&mut inventory.slots, &allowed, &stack, max_size)
+        };
+        let stored = self.get_bridge(addr)?.entry(name).or_default();
+        *stored += if import { i64::from(n) } else { -i64::from(n) };
+        Ok(n.into())
+    }
+
+    fn encode_stack(&self, stack: &Stack, with_detail: bool, api: Api) -> Value {
+        let mut table = Table::new();
+        table.insert("name".into(), stack.name.clone().into());
+        table.insert("count".into(), stack.count.into());
+        if let Some(nbt) = &stack.nbt {
+            table.insert(if api == Api::Plethora { "nbtHash" } else { "nbt" }.into(), nbt.clone().into());
+        }
+        if api == Api::Plethora {
+            table.insert("damage".into(), 0.into());
+        }
+        if with_detail {
+            let label = self.items.get(&stack.name).map_or_else(|| stack.name.clone(), |x| x.0.clone());
+            table.insert("displayName".into(), label.into());
+            table.insert("maxCount".into(), self.get_max_size(&stack.name).into());
+        }
+        table.into()
+    }
+
+    fn move_items(
+        &mut self,
+        from: &str,
+        to: &str,
+        from_slot: usize,
+        limit: Option<i32>,
+        to_slot: Option<usize>,
+    ) -> Result<i32, LocalStr> {
+        let source = self.get_inventory(from)?;
+        let Some(Some(mut stack)) = source.slots.get(from_slot).cloned() else { return Ok(0) };
+        stack.count = stack.count.min(limit.unwrap_or(i32::MAX));
+        let max_size = self.get_max_size(&stack.name);
+        let target = self.get_inventory(to)?;
+        let mut allowed = Vec::from_iter(to_slot.map_or(0..target.slots.len(), |slot| slot..slot + 1));
+        if allowed.iter().any(|&slot| slot >= target.slots.len()) {
+            return Err(local_fmt!("{}: slot out of range", to));
+        } else if from == to {
+            allowed.retain(|&slot| slot != from_slot)
+        }
+        let n = insert(&mut target.slots, &allowed, &stack, max_size);
+        remove(&mut self.get_inventory(from)?.slots, from_slot, n);
+        Ok(n)
+    }
+
+    fn move_fluid(
+        &mut self,
+        from: &str,
+        to: &str,
+        limit: Option<i64>,
+        fluid: Option<LocalStr>,
+    ) -> Result<i64, LocalStr> {
+        let source = self.get_tank(from)?;
+        let Some((i_source, (fluid, n_stored))) =
+            source.tanks.iter().enumerate().find_map(|(i, x)| {
+                x.clone().filter(|x| fluid.as_ref().is_none_or(|fluid| x.0 == *fluid)).map(|x| (i, x))
+            })
+        else {
+            return Ok(0);
+        };
+        let mut n = n_stored.min(limit.unwrap_or(i64::MAX));
+        let target = self.get_tank(to)?;
+        let capacity = target.capacity;
+        let i_target = (target.tanks.iter().position(|x| x.as_ref().is_some_and(|x| x.0 == fluid)))
+            .or_else(|| target.tanks.iter().position(|x| x.is_none()));
+        let Some(i_target) = i_target else { return Ok(0) };
+        let slot = target.tanks[i_target].get_or_insert((fluid, 0));
+        n = n.min(capacity - slot.1);
+        slot.1 += n;
+        if slot.1 == 0 {
+            target.tanks[i_target] = None
+        }
+        let source = &mut self.get_tank(from)?.tanks[i_source];
+        if let Some(x) = source {
+            x.1 -= n;
+            if x.1 <= 0 {
+                *source = None
+            }
+        }
+        Ok(n)
+    }
+
+    fn call(&mut self, addr: &str, args: Vec<Value>, api: Api) -> Result<Vec<Value>, LocalStr> {
+        let mut args = args.into_iter();
+        let method: LocalStr = required(arg(&mut args)?)?;
+        Ok(match &*method {
+            "size" => vec![self.get_inventory(addr)?.slots.len().into()],
+            "list" => {
+                let slots = self.get_inventory(addr)?.slots.clone();
+                let mut table = Table::new();
+                for (i, stack) in slots.iter().enumerate() {
+                    if let Some(stack) = stack {
+                        table.insert((i + 1).into(), self.encode_stack(stack, false, api));
+                    }
+                }
+                vec![table.into()]
+            }
+            x if x == api.detail_method() => {
+                let slot: usize = required(arg(&mut args)?)?;
+                let stack = self.get_inventory(addr)?.slots.get(slot.wrapping_sub(1)).cloned().flatten();
+                Vec::from_iter(stack.map(|x| self.encode_stack(&x, true, api)))
+            }
+            "pushItems" | "pullItems" => {
+                let other: LocalStr = required(arg(&mut args)?)?;
+                let from_slot: usize = required(arg(&mut args)?)?;
+                let limit = arg(&mut args)?;
+                let to_slot = arg::<usize>(&mut args)?.map(|x| x.wrapping_sub(1));
+                let (from, to) = if method == "pushItems" { (addr, &*other) } else { (&*other, addr) };
+                vec![self.move_items(from, to, from_slot.wrapping_sub(1), limit, to_slot)?.into()]
+            }
+            "listItems" => {
+                let items = Vec::from_iter(self.get_bridge(addr)?.clone().into_iter().filter(|x| x.1 > 0));
+                let items = items.into_iter().map(|(name, n)| {
+                    let (label, max_size) = self.items.get(&name).cloned().unwrap_or_else(|| (name.clone(), 64));
+                    Table::from([
+                        ("name".into(), name.into()),
+                        ("amount".into(), n.into()),
+                        ("displayName".into(), label.into()),
+                        ("maxStackSize".into(), max_size.into()),
+                    ])
+                    .into()
+                });
+                vec![vec_to_table(items.collect()).into()]
+            }
+            "importItem" | "exportItem" => {
+                let filter: Table = required(arg(&mut args)?)?;
+                let other: LocalStr = required(arg(&mut args)?)?;
+                vec![self.bridge_move(addr, filter, &other, method == "importItem")?.into()]
+            }
+            "tanks" => {
+                let tanks = self.get_tank(addr)?.tanks.iter().flatten().map(|(fluid, qty)| {
+                    let mut table = Table::new();
+                    table.insert("name".into(), fluid.clone().into());
+                    table.insert("amount".into(), (*qty).into());
+                    table.into()
+                });
+                vec![vec_to_table(tanks.collect()).into()]
+            }
+            "pushFluid" | "pullFluid" => {
+                let other: LocalStr = required(arg(&mut args)?)?;
+                let limit = arg(&mut args)?;
+                let fluid = arg(&mut args)?;
+                let (from, to) = if method == "pushFluid" { (addr, &*other) } else { (&*other, addr) };
+                vec![self.move_fluid(from, to, limit, fluid)?.into()]
+            }
+            _ => return Err(local_fmt!("{}: unsupported method: {}", addr, method)),
+        })
+    }
+
+    fn redstone(&mut self, mut request: Table) -> Result<Option<Value>, LocalStr> {
+        let op: LocalStr = table_remove(&mut request, "o")?;
+        let side: LocalStr = table_remove(&mut request, "s")?;
+        let addr = request.remove(&"p".into()).map(LocalStr::try_from).transpose()?;
+        let bit = request.remove(&"b".into()).map(i32::try_from).transpose()?;
+        let level = self.redstone.entry((addr, side)).or_default();
+        if op == "i" {
+            let value = match bit {
+                Some(bit) => (*level & bit != 0) as i32 * 15,
+                None => *level,
+            };
+            Ok(Some(value.into()))
+        } else {
+            let value: i32 = table_remove(&mut request, "v")?;
+            *level = match bit {
+                Some(bit) if value != 0 => *level | bit,
+                Some(bit) => *level & !bit,
+                None => value,
+            };
+            Ok(None)
+        }
+    }
+
+    // Finishes and starts machine recipes.
+    fn tick(&mut self, now: Instant) {
+        let items = &self.items;
+        let get_max_size = |name: &str| items.get(name).map_or(64, |x| x.1);
+        for inventory in self.inventories.values_mut() {
+            let Some(machine) = &mut inventory.machine else { continue };
+            if let Some((ready, outputs)) = &machine.busy {
+                if now < *ready {
+                    continue;
+                }
+                let mut slots = inventory.slots.clone();
+                let all_fit = outputs.iter().all(|(name, n)| {
+                    let stack = Stack { name: name.clone(), nbt: None, count: *n };
+                    insert(&mut slots, &machine.output_slots, &stack, get_max_size(name)) == *n
+                });
+                if !all_fit {
+                    continue;
+                }
+                inventory.slots = slots;
+                machine.busy = None
+            }
+            for recipe in &machine.recipes {
+                let n_available = |name: &LocalStr| {
+                    let stacks = machine.input_slots.iter().filter_map(|&slot| inventory.slots[slot].as_ref());
+                    stacks.filter(|x| x.name == *name && x.nbt.is_none()).map(|x| x.count).sum::<i32>()
+                };
+                if !recipe.inputs.iter().all(|(name, n)| n_available(name) >= *n) {
+                    continue;
+                }
+                for (name, n) in &recipe.inputs {
+                    let mut n = *n;
+                    for &slot in &machine.input_slots {
+                        let Some(stack) = &inventory.slots[slot] else { continue };
+                        if stack.name == *name && stack.nbt.is_none() {
+                            let n_removed = n.min(stack.count);
+                            remove(&mut inventory.slots, slot, n_removed);
+                            n -= n_removed
+                        }
+                    }
+                }
+                machine.busy = Some((now + recipe.time, recipe.outputs.clone()));
+                break;
+            }
+        }
+    }
+
+    fn execute(&mut self, mut request: Table, api: Api) -> Result<Option<Value>, LocalStr> {
+        let op: LocalStr = table_remove(&mut request, "o")?;
+        match &*op {
+            "l" => Ok(None),
+            "c" => {
+                let addr: LocalStr = table_remove(&mut request, "p")?;
+                let args = table_to_vec(table_remove(&mut request, "v")?)?;
+                Ok(Some(vec_to_table(self.call(&addr, args, api)?).into()))
+            }
+            "m" => {
+                let addr: LocalStr = table_remove(&mut request, "p")?;
+                let method: LocalStr = table_remove(&mut request, "m")?;
+                let mut results = Vec::new();
+                for args in table_to_vec(table_remove(&mut request, "v")?)? {
+                    let mut args = table_to_vec(args.try_into()?)?;
+                    args.insert(0, method.clone().into());
+                    results.push(match self.call(&addr, args, api) {
+                        Ok(x) => vec_to_table(x).into(),
+                        Err(e) => e.into(),
+                    })
+                }
+                Ok(Some(vec_to_table(results).into()))
+            }
+            "p" => {
+                let peripherals = self.get_peripherals().into_iter().map(|(addr, types)| {
+                    (addr.into(), vec_to_table(Vec::from_iter(types.into_iter().map(Value::S))).into())
+                });
+                Ok(Some(Table::from_iter(peripherals).into()))
+            }
+            "i" | "o" => {
+                request.insert("o".into(), op.into());
+                self.redstone(request)
+            }
+            _ => Err(local_fmt!("unsupported op: {}", op)),
+        }
+    }
+}
+
+fn on_packet(
+    world: &RefCell<World>,
+    client: &ClientFile,
+    protocol: &mut u8,
+    value: Value,
+    out: &mut Vec<u8>,
+) -> Result<(), LocalStr> {
+    match value {
+        // Login challenge, see `server::verify_response`.
+        Value::S(nonce) => {
+            let secret = client.secret.as_deref().ok_or_else(|| local_str!("server requires a secret"))?;
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+            mac.update(nonce.as_bytes());
+            serialize_versioned(*protocol, &Value::S(mac.finalize().into_bytes().encode_hex()), out);
+            Ok(())
+        }
+        // The protocol chosen by the server, which applies from the next packet on.
+        Value::T(mut ack) if ack.contains_key(&"v".into()) => {
+            *protocol = table_remove(&mut ack, "v")?;
+            Ok(())
+        }
+        Value::T(requests) => {
+            for request in table_to_vec(requests)? {
+                let mut request = Table::try_from(request)?;
+                let id = request.remove(&"i".into()).ok_or_else(|| local_str!("request without id"))?;
+                let mut response = Table::new();
+                response.insert("i".into(), id);
+                match world.borrow_mut().execute(request, client.api()) {
+                    Ok(Some(result)) => drop(response.insert("r".into(), result)),
+                    Ok(None) => (),
+                    Err(e) => drop(response.insert("e".into(), e.into())),
+                }
+                serialize_versioned(*protocol, &response.into(), out)
+            }
+            Ok(())
+        }
+        _ => Err(local_fmt!("invalid packet: {:?}", value)),
+    }
+}
+
+async fn run_client(world: &RefCell<World>, url: &str, id: usize, client: &ClientFile) -> Result<(), LocalStr> {
+    let (socket, _) = connect_async(url).await.map_err(|e| local_fmt!("error connecting: {}", e))?;
+    let (mut sink, mut stream) = socket.split();
+    let mut data = Vec::new();
+    let mut login = Value::S(client.name.as_str().into());
+    if client.protocol > PROTOCOL_TEXT {
+        let capabilities = world.borrow().get_capabilities(id, client.api()).into();
+        login = Value::T(Table::from([
+            ("n".into(), login),
+            ("v".into(), client.protocol.into()),
+            ("c".into(), capabilities),
+        ]))
+    }
+    serialize(&login, &mut data);
+    sink.send(Message::Binary(data)).await.map_err(|e| local_fmt!("error writing: {}", e))?;
+    let mut parser = Parser::new();
+    let mut protocol = PROTOCOL_TEXT;
+    while let Some(message) = stream.next().await {
+        let Message::Binary(data) = message.map_err(|e| local_fmt!("error reading: {}", e))? else { continue };
+        let mut out = Vec::new();
+        parser.shift(&data, &mut |x| on_packet(world, client, &mut protocol, x, &mut out))?;
+        parser.set_protocol(protocol);
+        if !out.is_empty() {
+            sink.send(Message::Binary(out)).await.map_err(|e| local_fmt!("error writing: {}", e))?
+        }
+    }
+    Err(local_str!("disconnected"))
+}
+
+// Reconnects after 3 seconds like `client.lua`.
+async fn client_main(tui: Rc<Tui>, world: Rc<RefCell<World>>, url: String, id: usize, client: ClientFile) {
+    loop {
+        if let Err(e) = run_client(&world, &url, id, &client).await {
+            tui.log(format!("sim {}: {}", client.name, e), 14)
+        }
+        sleep(Duration::from_secs(3)).await
+    }
+}
+
+async fn world_main(world: Rc<RefCell<World>>) {
+    loop {
+        sleep(Duration::from_millis(50)).await;
+        world.borrow_mut().tick(Instant::now())
+    }
+}
+
+pub struct Simulator {
+    _tasks: Vec<ChildTask<()>>,
+}
+
+pub fn run(tui: Rc<Tui>, path: &str) -> Option<Simulator> {
+    let file = read_to_string(path)
+        .map_err(|e| local_fmt!("{}: {}", path, e))
+        .and_then(|x| serde_json::from_str::<WorldFile>(&x).map_err(|e| local_fmt!("{}: {}", path, e)));
+    let world = file.and_then(|mut file| Ok((take(&mut file.url), take(&mut file.clients), World::load(file)?)));
+    let (url, clients, world) = match world {
+        Ok(x) => x,
+        Err(e) => {
+            tui.log(format!("simulator not started: {}", e), 14);
+            return None;
+        }
+    };
+    let world = Rc::new(RefCell::new(world));
+    let mut tasks = vec![spawn(world_main(world.clone()))];
+    for (id, client) in clients.into_iter().enumerate() {
+        tasks.push(spawn(client_main(tui.clone(), world.clone(), url.clone(), id, client)))
+    }
+    Some(Simulator { _tasks: tasks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_file::build_factory;
+    use std::fs::write;
+    use tokio::task::LocalSet;
+
+    // Named by test as well as by process, so that tests running at the same time don't share files.
+    fn temp_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("cc-remote-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // The listener is dropped right away for the server to bind the port again.
+    fn free_port() -> u16 { std::net::TcpListener::bind("[::]:0").unwrap().local_addr().unwrap().port() }
+
+    fn count(world: &World, addr: &str, name: Option<&str>) -> i32 {
+        let slots = &world.inventories[addr].slots;
+        slots.iter().flatten().filter(|x| name.is_none_or(|name| x.name == name)).map(|x| x.count).sum()
+    }
+
+    // Once enough stone is stored and nothing is left in the bus or the furnace.
+    fn is_settled(world: &World) -> bool {
+        count(world, "minecraft:chest_1", Some("minecraft:stone")) >= 16
+            && count(world, "minecraft:chest_0", None) == 0
+            && count(world, "minecraft:furnace_0", None) == 0
+            && world.inventories["minecraft:furnace_0"].machine.as_ref().is_some_and(|x| x.busy.is_none())
+    }
+
+    // Smelts cobblestone from storage into stone and checks that it ends up back in storage.
+    #[tokio::test]
+    async fn furnace_cycle() {
+        let dir = temp_dir("furnace_cycle");
+        let port = free_port();
+        let layout = dir.join("layout.json");
+        let detail_cache = dir.join("detail_cache.txt");
+        let config = serde_json::json!({
+            "port": port, "min_cycle_time": 0.2, "detail_cache": detail_cache,
+            "bus_accesses": [{ "client": "1a", "addr": "minecraft:chest_0" }],
+            "storages": [{
+                "type": "chest",
+                "accesses": [{ "client": "1a", "inv_addr": "minecraft:chest_1", "bus_addr": "minecraft:chest_0" }]
+            }],
+            "processes": [{
+                "type": "slotted", "name": "furnace",
+                "accesses": [{ "client": "1a", "inv_addr": "minecraft:furnace_0", "bus_addr": "minecraft:chest_0" }],
+                "input_slots": [0], "to_extract": { "slots": [2] },
+                "recipes": [{
+                    "outputs": { "stock": { "item": { "label": "Stone" }, "n_wanted": 16 } },
+                    "inputs": [{ "item": { "label": "Cobblestone" }, "slots": [[0, 1]] }],
+                    "max_sets": 8
+                }]
+            }]
+        });
+        write(&layout, config.to_string()).unwrap();
+        let world = serde_json::json!({
+            "url": format!("ws://127.0.0.1:{port}/"),
+            "clients": [{ "name": "1a" }],
+            "items": [
+                { "name": "minecraft:cobblestone", "label": "Cobblestone" },
+                { "name": "minecraft:stone", "label": "Stone" }
+            ],
+            "inventories": [
+                { "addr": "minecraft:chest_0", "size": 27 },
+                { "addr": "minecraft:chest_1", "size": 27, "contents": [{ "name": "minecraft:cobblestone", "count": 64 }] },
+                {
+                    "addr": "minecraft:furnace_0", "size": 3,
+                    "machine": {
+                        "input_slots": [0], "output_slots": [2],
+                        "recipes": [{
+                            "inputs": [["minecraft:cobblestone", 1]],
+                            "outputs": [["minecraft:stone", 1]],
+                            "time": 0.01
+                        }]
+                    }
+                }
+            ]
+        });
+        let mut file = serde_json::from_value::<WorldFile>(world).unwrap();
+        let (url, clients) = (take(&mut file.url), take(&mut file.clients));
+        let world = Rc::new(RefCell::new(World::load(file).unwrap()));
+        LocalSet::new()
+            .run_until(async {
+                let tui = Rc::<Tui>::default();
+                let _factory = build_factory(tui.clone(), layout.to_str().unwrap()).unwrap();
+                let mut tasks = vec![spawn(world_main(world.clone()))];
+                for (id, client) in clients.into_iter().enumerate() {
+                    tasks.push(spawn(client_main(tui.clone(), world.clone(), url.clone(), id, client)))
+                }
+                for _ in 0..100 {
+                    sleep(Duration::from_millis(100)).await;
+                    if is_settled(&world.borrow()) {
+                        break;
+                    }
+                }
+            })
+            .await;
+        let _ = std::fs::remove_dir_all(&dir);
+        let world = world.borrow();
+        assert!(is_settled(&world));
+        // Stock can overshoot by what was in the furnace when it was counted, but nothing is lost.
+        let n_stone = count(&world, "minecraft:chest_1", Some("minecraft:stone"));
+        assert_eq!(count(&world, "minecraft:chest_1", Some("minecraft:cobblestone")), 64 - n_stone);
+    }
+}
@@ -0,0 +1,160 @@
+use super::super::access::{BridgeAccess, ExpectedPeripherals};
+use super::super::action::{ActionFuture, Call};
+use super::super::detail_cache::DetailCache;
+use super::super::factory::Factory;
+use super::super::inventory::Inventory;
+use super::super::item::{Detail, DetailStack, Item};
+use super::super::lua_value::{call_result, table_remove, table_to_vec, Table, Value};
+use super::super::server::Server;
+use super::super::util::{alive, spawn};
+use super::{DepositResult, Extractor, IntoStorage, Provider, Storage};
+use abort_on_drop::ChildTask;
+use flexstr::LocalStr;
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+// A network behind an ME or RS bridge, addressed by item type rather than by slot.
+pub struct BridgeConfig {
+    pub accesses: Vec<BridgeAccess>,
+}
+
+pub struct BridgeStorage {
+    weak: Weak<RefCell<BridgeStorage>>,
+    config: BridgeConfig,
+    detail_cache: Rc<RefCell<DetailCache>>,
+    factory: Weak<RefCell<Factory>>,
+    server: Rc<RefCell<Server>>,
+}
+
+struct BridgeExtractor {
+    weak: Weak<RefCell<BridgeStorage>>,
+    item: Rc<Item>,
+}
+
+impl IntoStorage for BridgeConfig {
+    type Output = BridgeStorage;
+    fn into_storage(self, factory: &Factory) -> Rc<RefCell<Self::Output>> {
+        Rc::new_cyclic(|weak| {
+            RefCell::new(Self::Output {
+                weak: weak.clone(),
+                config: self,
+                detail_cache: factory.get_detail_cache().clone(),
+                factory: factory.get_weak().clone(),
+                server: factory.get_server().clone(),
+            })
+        })
+    }
+}
+
+// Bridges report NBT itself instead of the hash that identifies the item in other inventories.
+fn has_nbt(entry: &Table) -> bool {
+    match entry.get(&"nbt".into()) {
+        Some(Value::T(x)) => !x.is_empty(),
+        Some(Value::S(x)) => !x.is_empty(),
+        _ => false,
+    }
+}
+
+// Items without NBT only, each with the number stored and the listed detail.
+fn parse_listing(listing: Value) -> Result<Vec<(Rc<Item>, i32, Detail)>, LocalStr> {
+    let mut result = Vec::new();
+    for entry in table_to_vec(call_result(listing)?)? {
+        let mut entry = Table::try_from(entry)?;
+        if has_nbt(&entry) {
+            continue;
+        }
+        let name = table_remove(&mut entry, "name")?;
+        let n = match entry.remove(&"amount".into()) {
+            Some(x) => i64::try_from(x)?,
+            None => table_remove(&mut entry, "count")?,
+        };
+        let label = table_remove(&mut entry, "displayName")?;
+        let max_size = table_remove(&mut entry, "maxStackSize")?;
+        let item = Rc::new(Item { name, nbt_hash: None, damage: None });
+        result.push((item, n.clamp(0, i32::MAX.into()) as i32, Detail { label, max_size, others: entry }))
+    }
+    Ok(result)
+}
+
+fn item_filter(item: &Item, size: i32, bus_slot: usize, slot_key: &'static str) -> Value {
+    Table::from([
+        ("name".into(), item.name.clone().into()),
+        ("count".into(), size.into()),
+        (slot_key.into(), (bus_slot + 1).into()),
+    ])
+    .into()
+}
+
+impl Storage for BridgeStorage {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add("bridge", &self.config.accesses) }
+
+    fn update(&self) -> ChildTask<Result<(), LocalStr>> {
+        let server = self.server.borrow();
+        let action = server.call_with_failover(&self.config.accesses, |access| Call {
+            addr: access.bridge_addr.clone(),
+            args: vec!["listItems".into()],
+        });
+        let weak = self.weak.clone();
+        spawn(async move {
+            let listing = parse_listing(action.await?)?;
+            alive!(weak, this);
+            let mut detail_cache = this.detail_cache.borrow_mut();
+            upgrade_mut!(this.factory, factory);
+            for (item, n_stored, detail) in listing {
+                if n_stored <= 0 {
+                    continue;
+                }
+                let detail = detail_cache.query_or_insert(&item, || Rc::new(detail));
+                factory.register_stored_item(item.clone(), &detail).provide(Provider {
+                    priority: i32::MIN,
+                    n_provided: n_stored.into(),
+                    extractor: Rc::new(BridgeExtractor { weak: weak.clone(), item }),
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn cleanup(&mut self) {}
+
+    // After partial stacks in chests, before their empty slots.
+    fn deposit_priority(&mut self, item: &Rc<Item>, _: &Rc<Detail>) -> Option<i32> {
+        (item.nbt_hash.is_none() && item.damage.is_none()).then_some(0)
+    }
+
+    fn deposit(&mut self, stack: &DetailStack, bus_slot: usize) -> DepositResult {
+        let n_deposited = stack.size;
+        let server = self.server.borrow();
+        let access = server.load_balance(&self.config.accesses);
+        let action = ActionFuture::from(Call {
+            addr: access.bridge_addr.clone(),
+            args: vec![
+                "importItem".into(),
+                item_filter(&stack.item, n_deposited, bus_slot, "fromSlot"),
+                access.bus_addr.clone().into(),
+            ],
+        });
+        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        DepositResult { n_deposited, task: spawn(async move { action.await.map(|_| ()) }) }
+    }
+}
+
+impl Extractor for BridgeExtractor {
+    fn extract(&self, size: i32, bus_slot: usize) -> ChildTask<Result<(), LocalStr>> {
+        upgrade!(self.weak, this);
+        let server = this.server.borrow();
+        let access = server.load_balance(&this.config.accesses);
+        let action = ActionFuture::from(Call {
+            addr: access.bridge_addr.clone(),
+            args: vec![
+                "exportItem".into(),
+                item_filter(&self.item, size, bus_slot, "toSlot"),
+                access.bus_addr.clone().into(),
+            ],
+        });
+        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        spawn(async move { action.await.map(|_| ()) })
+    }
+}
@@ -1,4 +1,4 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::action::{ActionFuture, Call};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
@@ -6,7 +6,7 @@ use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{Detail, DetailStack, Item};
 use super::super::server::Server;
 use super::super::util::{alive, spawn};
-use super::{DepositResult, Extractor, IntoStorage, Provider, Storage};
+use super::{moved_as_expected, Capacity, DepositResult, Extractor, IntoStorage, Provider, Relist, Storage};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
 use std::{
@@ -36,7 +36,9 @@ pub struct ChestStorage {
     factory: Weak<RefCell<Factory>>,
     server: Rc<RefCell<Server>>,
     size: Option<usize>,
+    // Kept between listings, see `Relist`.
     stacks: Vec<Option<DetailStack>>,
+    relist: Relist,
     inv_slot_to_deposit: usize,
 }
 
@@ -59,6 +61,7 @@ impl IntoStorage for ChestConfig {
                 server: factory.get_server().clone(),
                 size: None,
                 stacks: Vec::new(),
+                relist: Relist::new(factory.config.relist_interval),
                 inv_slot_to_deposit: 0,
             })
         })
@@ -66,13 +69,19 @@ impl IntoStorage for ChestConfig {
 }
 
 impl Storage for ChestStorage {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add("chest", &self.config.accesses) }
+
     fn update(&self) -> ChildTask<Result<(), LocalStr>> {
-        let stacks = list_inventory(self);
+        let stacks = self.relist.is_due().then(|| list_inventory(self));
         let weak = self.weak.clone();
         spawn(async move {
-            let stacks = stacks.await?;
-            alive_mut!(weak, this);
-            this.stacks = stacks;
+            if let Some(stacks) = stacks {
+                let stacks = stacks.await?;
+                alive_mut!(weak, this);
+                this.stacks = stacks;
+                this.relist.listed()
+            }
+            alive!(weak, this);
             upgrade_mut!(this.factory, factory);
             for (inv_slot, stack) in this.stacks.iter().enumerate() {
                 if let Some(stack) = stack {
@@ -87,7 +96,24 @@ impl Storage for ChestStorage {
         })
     }
 
-    fn cleanup(&mut self) { self.stacks.clear() }
+    fn cleanup(&mut self) {
+        if self.relist.is_due() {
+            self.stacks.clear()
+        }
+    }
+
+    fn capacity(&self) -> Option<Capacity> {
+        let mut result = Capacity { n_slots: self.stacks.len(), ..Capacity::default() };
+        for stack in &self.stacks {
+            if let Some(stack) = stack {
+                result.n_free_items += (self.config.max_size(stack.detail.max_size) - stack.size).max(0) as i64
+            } else {
+                result.n_free_slots += 1;
+                result.n_free_items += self.config.max_size(64) as i64
+            }
+        }
+        Some(result)
+    }
 
     fn deposit_priority(&mut self, item: &Rc<Item>, detail: &Rc<Detail>) -> Option<i32> {
         let mut empty_slot = None;
@@ -139,7 +165,15 @@ impl Storage for ChestStorage {
             ],
         });
         server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        let task = spawn(async move { action.await.map(|_| ()) });
+        let weak = self.weak.clone();
+        let task = spawn(async move {
+            let result = action.await;
+            if !moved_as_expected(&result, n_deposited) {
+                alive_mut!(weak, this);
+                this.relist.invalidate()
+            }
+            result.map(|_| ())
+        });
         DepositResult { n_deposited, task }
     }
 }
@@ -163,8 +197,12 @@ impl Extractor for ChestExtractor {
         server.enqueue_request_group(&access.client, vec![action.clone().into()]);
         let weak = self.weak.clone();
         spawn(async move {
-            action.await?;
+            let result = action.await;
             alive_mut!(weak, this);
+            if !moved_as_expected(&result, size) {
+                this.relist.invalidate()
+            }
+            result?;
             let inv_stack = &mut this.stacks[inv_slot];
             let inv_size = &mut inv_stack.as_mut().unwrap().size;
             *inv_size -= size;
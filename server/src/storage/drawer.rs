@@ -1,4 +1,4 @@
-use super::super::access::BusAccess;
+use super::super::access::{BusAccess, ExpectedPeripherals};
 use super::super::action::{ActionFuture, Call};
 use super::super::detail_cache::DetailCache;
 use super::super::factory::Factory;
@@ -6,7 +6,7 @@ use super::super::inventory::{list_inventory, Inventory};
 use super::super::item::{Detail, DetailStack, Filter, Item};
 use super::super::server::Server;
 use super::super::util::{alive, spawn};
-use super::{DepositResult, Extractor, IntoStorage, Provider, Storage};
+use super::{moved_as_expected, DepositResult, Extractor, IntoStorage, Provider, Relist, Storage};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
 use std::{
@@ -26,6 +26,9 @@ pub struct DrawerStorage {
     factory: Weak<RefCell<Factory>>,
     server: Rc<RefCell<Server>>,
     size: Option<usize>,
+    // Kept between listings, see `Relist`.
+    stacks: Vec<Option<DetailStack>>,
+    relist: Relist,
 }
 
 struct DrawerExtractor {
@@ -46,22 +49,31 @@ impl IntoStorage for DrawerConfig {
                 factory: factory.get_weak().clone(),
                 server: factory.get_server().clone(),
                 size: None,
+                stacks: Vec::new(),
+                relist: Relist::new(factory.config.relist_interval),
             })
         })
     }
 }
 
 impl Storage for DrawerStorage {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add("drawer", &self.config.accesses) }
+
     fn update(&self) -> ChildTask<Result<(), LocalStr>> {
-        let stacks = list_inventory(self);
+        let stacks = self.relist.is_due().then(|| list_inventory(self));
         let weak = self.weak.clone();
         spawn(async move {
-            let stacks = stacks.await?;
+            if let Some(stacks) = stacks {
+                let stacks = stacks.await?;
+                alive_mut!(weak, this);
+                this.stacks = stacks;
+                this.relist.listed()
+            }
             alive!(weak, this);
             upgrade_mut!(this.factory, factory);
-            for (inv_slot, stack) in stacks.into_iter().enumerate() {
+            for (inv_slot, stack) in this.stacks.iter().enumerate() {
                 if let Some(stack) = stack {
-                    factory.register_stored_item(stack.item, &stack.detail).provide(Provider {
+                    factory.register_stored_item(stack.item.clone(), &stack.detail).provide(Provider {
                         priority: i32::MIN,
                         n_provided: stack.size.into(),
                         extractor: Rc::new(DrawerExtractor { weak: weak.clone(), inv_slot }),
@@ -72,7 +84,11 @@ impl Storage for DrawerStorage {
         })
     }
 
-    fn cleanup(&mut self) {}
+    fn cleanup(&mut self) {
+        if self.relist.is_due() {
+            self.stacks.clear()
+        }
+    }
 
     fn deposit_priority(&mut self, item: &Rc<Item>, detail: &Rc<Detail>) -> Option<i32> {
         for filter in &self.config.filters {
@@ -85,6 +101,11 @@ impl Storage for DrawerStorage {
 
     fn deposit(&mut self, stack: &DetailStack, bus_slot: usize) -> DepositResult {
         let n_deposited = stack.size;
+        // The drawer picks the slot; without one holding the item yet, it is only known after listing again.
+        match self.stacks.iter_mut().flatten().find(|x| x.item == stack.item) {
+            Some(inv_stack) => inv_stack.size += n_deposited,
+            None => self.relist.invalidate(),
+        }
         let server = self.server.borrow();
         let access = server.load_balance(&self.config.accesses);
         let action = ActionFuture::from(Call {
@@ -92,13 +113,22 @@ impl Storage for DrawerStorage {
             args: vec!["pushItems".into(), access.inv_addr.clone().into(), (bus_slot + 1).into(), n_deposited.into()],
         });
         server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        let task = spawn(async move { action.await.map(|_| ()) });
+        let weak = self.weak.clone();
+        let task = spawn(async move {
+            let result = action.await;
+            if !moved_as_expected(&result, n_deposited) {
+                alive_mut!(weak, this);
+                this.relist.invalidate()
+            }
+            result.map(|_| ())
+        });
         DepositResult { n_deposited, task }
     }
 }
 
 impl Extractor for DrawerExtractor {
     fn extract(&self, size: i32, bus_slot: usize) -> ChildTask<Result<(), LocalStr>> {
+        let inv_slot = self.inv_slot;
         upgrade!(self.weak, this);
         let server = this.server.borrow();
         let access = server.load_balance(&this.config.accesses);
@@ -107,12 +137,27 @@ impl Extractor for DrawerExtractor {
             args: vec![
                 "pullItems".into(),
                 access.inv_addr.clone().into(),
-                (self.inv_slot + 1).into(),
+                (inv_slot + 1).into(),
                 size.into(),
                 (bus_slot + 1).into(),
             ],
         });
         server.enqueue_request_group(&access.client, vec![action.clone().into()]);
-        spawn(async move { action.await.map(|_| ()) })
+        let weak = self.weak.clone();
+        spawn(async move {
+            let result = action.await;
+            alive_mut!(weak, this);
+            if !moved_as_expected(&result, size) {
+                this.relist.invalidate()
+            }
+            result?;
+            let inv_stack = &mut this.stacks[inv_slot];
+            let inv_size = &mut inv_stack.as_mut().unwrap().size;
+            *inv_size -= size;
+            if *inv_size <= 0 {
+                *inv_stack = None;
+            }
+            Ok(())
+        })
     }
 }
@@ -1,12 +1,17 @@
+use super::access::ExpectedPeripherals;
 use super::factory::Factory;
-use super::item::{Detail, DetailStack, Item};
+use super::item::{Detail, DetailStack, Filter, Item};
+use super::lua_value::{call_result, Value};
 use abort_on_drop::ChildTask;
 use flexstr::LocalStr;
 use std::{
     cell::{Cell, RefCell},
     cmp::Ordering,
+    ops::AddAssign,
     rc::Rc,
+    time::Duration,
 };
+use tokio::time::Instant;
 
 pub struct DepositResult {
     pub n_deposited: i32,
@@ -18,6 +23,94 @@ pub trait Storage: 'static {
     fn cleanup(&mut self);
     fn deposit_priority(&mut self, item: &Rc<Item>, detail: &Rc<Detail>) -> Option<i32>;
     fn deposit(&mut self, stack: &DetailStack, bus_slot: usize) -> DepositResult;
+    fn expect_peripherals(&self, _: &mut ExpectedPeripherals) {}
+    // Free space as of the last `update`, if the storage can tell.
+    fn capacity(&self) -> Option<Capacity> { None }
+    // Destroys what it receives, so it loses to every other storage in the same tier.
+    fn is_void(&self) -> bool { false }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Capacity {
+    pub n_slots: usize,
+    pub n_free_slots: usize,
+    // Fitting into partial stacks and free slots.
+    pub n_free_items: i64,
+}
+
+impl Capacity {
+    pub fn used_fraction(&self) -> f64 {
+        if self.n_slots == 0 {
+            return 0.;
+        }
+        1. - self.n_free_slots as f64 / self.n_slots as f64
+    }
+
+    // With a bar of slots used, e.g. `[#####---------------] 25% full, 96 slots (6144 items) free`.
+    pub fn describe(&self) -> String {
+        let used = self.used_fraction();
+        let n_bars = (used * 20.).round() as usize;
+        let bar = format!("{}{}", "#".repeat(n_bars), "-".repeat(20 - n_bars));
+        format!("[{}] {:.0}% full, {} slots ({} items) free", bar, used * 100., self.n_free_slots, self.n_free_items)
+    }
+}
+
+impl AddAssign for Capacity {
+    fn add_assign(&mut self, other: Self) {
+        self.n_slots += other.n_slots;
+        self.n_free_slots += other.n_free_slots;
+        self.n_free_items += other.n_free_items
+    }
+}
+
+// Compared across storages allowing an item; the greatest one receives it.
+pub type Rank = (bool, i32, bool, i32);
+
+// Which items a storage receives, on top of its own `deposit_priority`.
+#[derive(Default)]
+pub struct Route {
+    // Empty to allow everything.
+    pub allow: Vec<Filter>,
+    pub deny: Vec<Filter>,
+    // Higher tiers are filled first, whatever `deposit_priority` says.
+    pub tier: i32,
+    // Only receives items that no storage without it takes.
+    pub overflow: bool,
+    pub no_nbt: bool,
+    // Only receives an item while more than this many are stored, e.g. for a trash can.
+    pub above: Option<i32>,
+}
+
+impl Route {
+    pub fn allows(&self, item: &Item, detail: &Detail, n_stored: i32) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|x| x.apply(item, detail)))
+            && !self.deny.iter().any(|x| x.apply(item, detail))
+            && !(self.no_nbt && item.nbt_hash.is_some())
+            && self.above.is_none_or(|x| n_stored > x)
+    }
+
+    pub fn rank(&self, deposit_priority: i32, is_void: bool) -> Rank {
+        (!self.overflow, self.tier, !is_void, deposit_priority)
+    }
+}
+
+// When a storage that keeps its stacks between cycles has to list its inventory again.
+struct Relist {
+    interval: Duration,
+    // `None` until the first listing, and once the kept stacks may be wrong.
+    last: Option<Instant>,
+}
+
+impl Relist {
+    fn new(interval: Duration) -> Self { Relist { interval, last: None } }
+    fn is_due(&self) -> bool { self.last.is_none_or(|x| x.elapsed() >= self.interval) }
+    fn listed(&mut self) { self.last = Some(Instant::now()) }
+    fn invalidate(&mut self) { self.last = None }
+}
+
+// Whether a push or pull moved as many items as the kept stacks assume.
+fn moved_as_expected(result: &Result<Value, LocalStr>, n: i32) -> bool {
+    result.as_ref().is_ok_and(|x| call_result::<i32>(x.clone()).is_ok_and(|x| x == n))
 }
 
 pub trait IntoStorage {
@@ -49,7 +142,11 @@ impl Ord for Provider {
     fn cmp(&self, other: &Self) -> Ordering { self.priority.cmp(&other.priority) }
 }
 
+mod bridge;
 mod chest;
 mod drawer;
+mod trash;
+pub use bridge::*;
 pub use chest::*;
 pub use drawer::*;
+pub use trash::*;
@@ -0,0 +1,50 @@
+use super::super::access::{BusAccess, ExpectedPeripherals};
+use super::super::action::{ActionFuture, Call};
+use super::super::factory::Factory;
+use super::super::inventory::Inventory;
+use super::super::item::{Detail, DetailStack, Item};
+use super::super::server::Server;
+use super::super::util::spawn;
+use super::{DepositResult, IntoStorage, Storage};
+use abort_on_drop::ChildTask;
+use flexstr::LocalStr;
+use std::{cell::RefCell, rc::Rc};
+
+// An inventory that voids what it receives, e.g. a trash can. Usually routed with `Route::above`.
+pub struct TrashConfig {
+    pub accesses: Vec<BusAccess>,
+}
+
+pub struct TrashStorage {
+    config: TrashConfig,
+    server: Rc<RefCell<Server>>,
+}
+
+impl IntoStorage for TrashConfig {
+    type Output = TrashStorage;
+    fn into_storage(self, factory: &Factory) -> Rc<RefCell<Self::Output>> {
+        Rc::new(RefCell::new(Self::Output { config: self, server: factory.get_server().clone() }))
+    }
+}
+
+impl Storage for TrashStorage {
+    fn expect_peripherals(&self, expected: &mut ExpectedPeripherals) { expected.add("trash", &self.config.accesses) }
+    fn update(&self) -> ChildTask<Result<(), LocalStr>> { spawn(async { Ok(()) }) }
+    fn cleanup(&mut self) {}
+
+    // Any other storage in the same tier is filled first; give it a higher tier to void items that it allows.
+    fn deposit_priority(&mut self, _: &Rc<Item>, _: &Rc<Detail>) -> Option<i32> { Some(0) }
+    fn is_void(&self) -> bool { true }
+
+    fn deposit(&mut self, stack: &DetailStack, bus_slot: usize) -> DepositResult {
+        let n_deposited = stack.size;
+        let server = self.server.borrow();
+        let access = server.load_balance(&self.config.accesses);
+        let action = ActionFuture::from(Call {
+            addr: access.bus_addr.clone(),
+            args: vec!["pushItems".into(), access.inv_addr.clone().into(), (bus_slot + 1).into(), n_deposited.into()],
+        });
+        server.enqueue_request_group(&access.client, vec![action.clone().into()]);
+        DepositResult { n_deposited, task: spawn(async move { action.await.map(|_| ()) }) }
+    }
+}